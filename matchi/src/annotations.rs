@@ -0,0 +1,111 @@
+//! Annotations given in a separate file (--annotations), instead of attributes in the netlist.
+//!
+//! The file is in TOML format (or JSON, if its name ends with '.json'), with one table per module,
+//! e.g.,
+//! ```toml
+//! [top]
+//! matchi_prop = "PINI"
+//! matchi_strat = "composite_top"
+//! matchi_arch = "loopy"
+//! matchi_shares = 2
+//!
+//! [top.wires]
+//! clk = { matchi_type = "clock" }
+//! a = { matchi_type = "sharings_dense", matchi_active = "in_valid" }
+//!
+//! [top.cells]
+//! state_reg = { matchi_stop_glitches = 1 }
+//! ```
+//! The annotations are merged into the attributes of the netlist, overriding the attributes with
+//! the same name.
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use yosys_netlist_json as yosys;
+
+type Attributes = BTreeMap<String, yosys::AttributeVal>;
+
+/// Integer-valued attributes. In the netlist, their value is a binary string, hence they must be
+/// given as integers in the annotations file.
+const INT_ATTRIBUTES: &[&str] = &[
+    "matchi_shares",
+    "matchi_share",
+    "matchi_lat",
+    "matchi_latency",
+    "matchi_stop_glitches",
+];
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+struct ModuleAnnotations {
+    /// Annotations of the ports and other wires.
+    #[serde(default)]
+    wires: BTreeMap<String, Attributes>,
+    #[serde(default)]
+    cells: BTreeMap<String, Attributes>,
+    /// Annotations of the module itself.
+    #[serde(flatten)]
+    attributes: Attributes,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    modules: BTreeMap<String, ModuleAnnotations>,
+}
+
+impl Annotations {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read annotations file '{}'.", path))?;
+        let modules = if path.ends_with(".json") {
+            serde_json::from_str(&content).map_err(anyhow::Error::from)
+        } else {
+            toml::from_str(&content).map_err(anyhow::Error::from)
+        }
+        .with_context(|| format!("Could not parse annotations file '{}'.", path))?;
+        Ok(Self { modules })
+    }
+    /// Merge the annotations into the attributes of the netlist.
+    pub fn apply(self, netlist: &mut yosys::Netlist) -> Result<()> {
+        for (module_name, annotations) in self.modules {
+            let module = netlist.modules.get_mut(&module_name).ok_or_else(|| {
+                anyhow!("Annotated module {} is not in the netlist.", module_name)
+            })?;
+            check_int_attributes(&annotations.attributes, &format!("module {}", module_name))?;
+            module.attributes.extend(annotations.attributes);
+            for (wire, attributes) in annotations.wires {
+                check_int_attributes(&attributes, &format!("wire {}.{}", module_name, wire))?;
+                module
+                    .netnames
+                    .get_mut(&wire)
+                    .ok_or_else(|| anyhow!("Annotated wire {}.{} not found.", module_name, wire))?
+                    .attributes
+                    .extend(attributes);
+            }
+            for (cell, attributes) in annotations.cells {
+                check_int_attributes(&attributes, &format!("cell {}.{}", module_name, cell))?;
+                module
+                    .cells
+                    .get_mut(&cell)
+                    .ok_or_else(|| anyhow!("Annotated cell {}.{} not found.", module_name, cell))?
+                    .attributes
+                    .extend(attributes);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that the integer-valued attributes are not given as strings (which would be parsed as
+/// binary numbers).
+fn check_int_attributes(attributes: &Attributes, location: &str) -> Result<()> {
+    for (name, value) in attributes {
+        if INT_ATTRIBUTES.contains(&name.as_str()) && !matches!(value, yosys::AttributeVal::N(_)) {
+            bail!(
+                "Annotation {} of {} must be an integer (e.g., {} = 1), not a string.",
+                name,
+                location,
+                name
+            );
+        }
+    }
+    Ok(())
+}
@@ -1,10 +1,13 @@
-//! Analysis of vcd files as a series of state, for each clock cycle.
+//! Analysis of vcd (or fst) files as a series of state, for each clock cycle.
 
 use super::WireValue;
 use crate::type_utils::new_id;
 use anyhow::{anyhow, bail, Result};
 use fnv::FnvHashMap as HashMap;
+use itertools::Either;
 use std::borrow::Borrow;
+use std::collections::VecDeque;
+use std::io::{BufRead, Seek};
 
 /// State of a circuit at one clock cycle.
 pub type State = VarVec<VarState>;
@@ -20,46 +23,85 @@ pub enum VarState {
 // Id of a variable.
 new_id!(VarId, VarVec, VarSlice);
 
-pub type IdCode = vcd::IdCode;
+/// Parser of the simulation waveform, either in the VCD or in the FST format.
+pub enum WaveformParser<R: BufRead + Seek> {
+    Vcd(vcd::Parser<R>),
+    Fst(Box<fst_reader::FstReader<R>>),
+}
+
+/// Identifier of a variable in the waveform (the id code for VCD, the signal handle for FST).
+type VarCode = u64;
+
+/// Variable of the waveform.
+#[derive(Debug, Clone, Copy)]
+struct Var {
+    code: VarCode,
+    size: u32,
+}
 
-pub struct VcdParsedHeader<R: std::io::BufRead> {
-    parser: vcd::Parser<R>,
-    used_vars: VarVec<vcd::Var>,
-    clock: IdCode,
-    var_names: HashMap<Vec<String>, (vcd::Var, Option<VarId>)>,
+pub struct VcdParsedHeader<R: BufRead + Seek> {
+    parser: WaveformParser<R>,
+    used_vars: VarVec<Var>,
+    clock: VarCode,
+    var_names: HashMap<Vec<String>, Var>,
+    /// Variable ids of the used vars (aliased variables share the same code).
+    var_ids: HashMap<VarCode, VarId>,
 }
 
-#[derive(Debug, Clone)]
+/// States of the clock cycles, parsed lazily from the waveform: only the states of the cycles
+/// that are still needed by the simulation are kept in memory.
 pub struct VcdParsedStates {
-    states: Vec<State>,
+    /// Remaining events of the waveform (None once they are exhausted).
+    events: Option<Box<dyn Iterator<Item = Result<WaveEvent>>>>,
+    sampler: ClockSampler,
+    /// States of the cycles starting from offset.
+    states: VecDeque<State>,
+    offset: usize,
+}
+
+/// Change in the waveform.
+enum WaveEvent {
+    /// Start of a new time step.
+    Timestamp,
+    Scalar(VarCode, vcd::Value),
+    /// Vector value (BE, possibly truncated).
+    Vector(VarCode, Vec<vcd::Value>),
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct VarOffsetId(VarId, usize);
 
-impl<R: std::io::BufRead> VcdParsedHeader<R> {
-    pub fn new(mut parser: vcd::Parser<R>, clock: &[impl Borrow<str>]) -> Result<Self> {
-        let header = parser.parse_header()?;
-        let clock = header
-            .find_var(clock)
+impl<R: BufRead + Seek> VcdParsedHeader<R> {
+    pub fn new(mut parser: WaveformParser<R>, clock: &[impl Borrow<str>]) -> Result<Self> {
+        let var_names = match &mut parser {
+            WaveformParser::Vcd(parser) => vcd_var_names(&parser.parse_header()?),
+            WaveformParser::Fst(reader) => fst_var_names(reader)?,
+        };
+        let clock_path = clock
+            .iter()
+            .map(|name| normalize_name(name.borrow()))
+            .collect::<Vec<_>>();
+        let clock = var_names
+            .get(&clock_path)
             .ok_or_else(|| anyhow!("Did not find clock {} in vcd file.", clock.join(".")))?
             .code;
-        let var_names = var_names(&header);
         Ok(Self {
             parser,
             used_vars: VarVec::new(),
             clock,
             var_names,
+            var_ids: HashMap::default(),
         })
     }
     pub fn add_var(&mut self, path: &[String]) -> Result<VarId> {
-        let res = self
+        let var = *self
             .var_names
-            .get_mut(path)
+            .get(path)
             .ok_or_else(|| anyhow!("No variable {} in vcd.", path.join(".")))?;
-        Ok(*res
-            .1
-            .get_or_insert_with(|| self.used_vars.push(res.0.clone())))
+        Ok(*self
+            .var_ids
+            .entry(var.code)
+            .or_insert_with(|| self.used_vars.push(var)))
     }
     pub fn add_var_offset(&mut self, path: &[String], offset: usize) -> Result<VarOffsetId> {
         let var_id = self.add_var(path)?;
@@ -72,18 +114,70 @@ impl<R: std::io::BufRead> VcdParsedHeader<R> {
         }
         Ok(VarOffsetId(var_id, offset))
     }
-    pub fn get_states(self) -> Result<VcdParsedStates> {
-        let states = clocked_states2(
-            &self.used_vars,
-            self.clock,
-            self.parser.map(|cmd| cmd.map_err(Into::into)),
-        )?;
-        Ok(VcdParsedStates { states })
+    pub fn get_states(self) -> Result<VcdParsedStates>
+    where
+        R: Send + 'static,
+    {
+        let events: Box<dyn Iterator<Item = Result<WaveEvent>>> = match self.parser {
+            WaveformParser::Vcd(parser) => Box::new(parser.filter_map(|command| match command {
+                Ok(vcd::Command::ChangeScalar(id_code, value)) => {
+                    Some(Ok(WaveEvent::Scalar(u64::from(id_code), value)))
+                }
+                Ok(vcd::Command::ChangeVector(id_code, value)) => {
+                    Some(Ok(WaveEvent::Vector(u64::from(id_code), value.into())))
+                }
+                Ok(vcd::Command::Timestamp(_)) => Some(Ok(WaveEvent::Timestamp)),
+                Ok(_) => None,
+                Err(error) => Some(Err(error.into())),
+            })),
+            WaveformParser::Fst(reader) => {
+                let handles = self
+                    .var_ids
+                    .keys()
+                    .chain(std::iter::once(&self.clock))
+                    .map(|code| fst_reader::FstSignalHandle::from_index(*code as usize))
+                    .collect();
+                Box::new(fst_events(*reader, handles))
+            }
+        };
+        Ok(VcdParsedStates {
+            events: Some(events),
+            sampler: ClockSampler::new(self.used_vars, self.var_ids, self.clock),
+            states: VecDeque::new(),
+            offset: 0,
+        })
     }
 }
+
 impl VcdParsedStates {
+    /// Parse the waveform up to the given cycle, returns false if the waveform has less cycles.
+    pub fn load(&mut self, cycle: usize) -> Result<bool> {
+        assert!(cycle >= self.offset, "Cycle {} was discarded.", cycle);
+        while self.offset + self.states.len() <= cycle {
+            let Some(events) = self.events.as_mut() else {
+                return Ok(false);
+            };
+            if let Some(event) = events.next() {
+                if let Some(state) = self.sampler.event(event?)? {
+                    self.states.push_back(state);
+                }
+            } else {
+                // The state at the end of the waveform is the last cycle.
+                self.events = None;
+                self.states.push_back(self.sampler.current_state.clone());
+            }
+        }
+        Ok(true)
+    }
+    /// Discard the states of the cycles before the given one.
+    pub fn discard_before(&mut self, cycle: usize) {
+        while self.offset < cycle && !self.states.is_empty() {
+            self.states.pop_front();
+            self.offset += 1;
+        }
+    }
     pub fn get_var(&self, var_id: VarId, cycle: usize) -> &VarState {
-        &self.states[cycle][var_id]
+        &self.states[cycle - self.offset][var_id]
     }
     pub fn get_var_offset(&self, var_offset_id: VarOffsetId, cycle: usize) -> Option<WireValue> {
         match &self.get_var(var_offset_id.0, cycle) {
@@ -93,15 +187,20 @@ impl VcdParsedStates {
             x => unreachable!("expected no offset, found {:?}, {:?}", var_offset_id, x),
         }
     }
-    pub fn len(&self) -> usize {
-        self.states.len()
+}
+
+impl std::fmt::Debug for VcdParsedStates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VcdParsedStates")
+            .field("states", &self.states)
+            .field("offset", &self.offset)
+            .finish_non_exhaustive()
     }
 }
 
 /// Maps the state of a vector signal from the vcd (truncated, BE) to the representation used in
 /// the states (not trucated, LE).
-fn pad_vec_and_reverse(vec: vcd::Vector, size: u32) -> Vec<vcd::Value> {
-    let mut vec: Vec<vcd::Value> = vec.into();
+fn pad_vec_and_reverse(mut vec: Vec<vcd::Value>, size: u32) -> Vec<vcd::Value> {
     // We need to reverse order of bits since last one in binary writing is at offset 0.
     // Then we pad since leading '0', 'x' or 'z' are not always written.
     let padding_value = if vec[0] == vcd::Value::V1 {
@@ -114,7 +213,7 @@ fn pad_vec_and_reverse(vec: vcd::Vector, size: u32) -> Vec<vcd::Value> {
     vec
 }
 
-fn var_names(header: &vcd::Header) -> HashMap<Vec<String>, (vcd::Var, Option<VarId>)> {
+fn vcd_var_names(header: &vcd::Header) -> HashMap<Vec<String>, Var> {
     let mut res = HashMap::default();
     let mut remaining_items = header
         .items
@@ -136,7 +235,11 @@ fn var_names(header: &vcd::Header) -> HashMap<Vec<String>, (vcd::Var, Option<Var
             }
             vcd::ScopeItem::Var(var) => {
                 path.push(normalize_name(&var.reference));
-                res.insert(path, (var.clone(), None));
+                let var = Var {
+                    code: u64::from(var.code),
+                    size: var.size,
+                };
+                res.insert(path, var);
             }
             _ => {}
         }
@@ -144,6 +247,49 @@ fn var_names(header: &vcd::Header) -> HashMap<Vec<String>, (vcd::Var, Option<Var
     res
 }
 
+fn fst_var_names<R: BufRead + Seek>(
+    reader: &mut fst_reader::FstReader<R>,
+) -> Result<HashMap<Vec<String>, Var>> {
+    let mut res = HashMap::default();
+    let mut path = vec![];
+    reader.read_hierarchy(|entry| match entry {
+        fst_reader::FstHierarchyEntry::Scope { name, .. } => path.push(normalize_name(&name)),
+        fst_reader::FstHierarchyEntry::UpScope => {
+            path.pop();
+        }
+        fst_reader::FstHierarchyEntry::Var {
+            name,
+            length,
+            handle,
+            ..
+        } => {
+            // Vector variables may be named with their range (e.g., "data [7:0]").
+            let name = match name.rsplit_once('[') {
+                Some((base, range))
+                    if range.strip_suffix(']').is_some_and(|range| {
+                        range.chars().all(|c| c.is_ascii_digit() || c == ':')
+                    }) =>
+                {
+                    base.trim_end()
+                }
+                _ => &name,
+            };
+            let var_path = path
+                .iter()
+                .cloned()
+                .chain(std::iter::once(normalize_name(name)))
+                .collect();
+            let var = Var {
+                code: handle.get_index() as VarCode,
+                size: length,
+            };
+            res.insert(var_path, var);
+        }
+        _ => {}
+    })?;
+    Ok(res)
+}
+
 fn normalize_name(name: &str) -> String {
     // Remove leading backslash, in case the vcd is encoded using the "escaped
     // identifier" syntax of verilog.
@@ -152,73 +298,150 @@ fn normalize_name(name: &str) -> String {
     name.strip_prefix('\\').unwrap_or(name).replace(r"\\", r"\")
 }
 
-fn clocked_states2(
-    used_vars: &VarVec<vcd::Var>,
-    clock: vcd::IdCode,
-    commands: impl Iterator<Item = Result<vcd::Command>>,
-) -> Result<Vec<State>> {
-    let max_idcode = used_vars
-        .iter()
-        .map(|var| u64::from(var.code))
-        .max()
-        .unwrap_or(0);
-    let mut idcode2var_id = vec![None; max_idcode as usize + 1];
-    for (var_id, var) in used_vars.iter_enumerated() {
-        idcode2var_id[u64::from(var.code) as usize] = Some(var_id);
+/// Sampling of the waveform at the rising edges of the clock: the state of a cycle is the state
+/// of the variables just before the rising edge.
+struct ClockSampler {
+    used_vars: VarVec<Var>,
+    var_ids: HashMap<VarCode, VarId>,
+    clock: VarCode,
+    current_state: State,
+    /// State at the end of the previous timestamp.
+    previous_state: State,
+    clk_state: vcd::Value,
+    started: bool,
+    n_cycles: usize,
+}
+
+impl ClockSampler {
+    fn new(used_vars: VarVec<Var>, var_ids: HashMap<VarCode, VarId>, clock: VarCode) -> Self {
+        let current_state = VarVec::from_vec(vec![VarState::Uninit; used_vars.len()]);
+        Self {
+            used_vars,
+            var_ids,
+            clock,
+            previous_state: current_state.clone(),
+            current_state,
+            clk_state: vcd::Value::X,
+            started: false,
+            n_cycles: 0,
+        }
     }
-    let get_var_id = |idcode| {
-        idcode2var_id
-            .get(u64::from(idcode) as usize)
-            .copied()
-            .flatten()
-    };
-    let mut states = Vec::new();
-    let mut current_state = VarVec::from_vec(vec![VarState::Uninit; used_vars.len()]);
-    //let mut previous_state = current_state.clone();
-    let mut previous_state = current_state.clone();
-    let mut clk_state = vcd::Value::X;
-    let mut started = false;
-    for command in commands {
-        match command? {
-            vcd::Command::ChangeScalar(id_code, value) => {
-                if id_code == clock {
+    /// Process an event, returns the state of the cycle if it is a rising edge of the clock.
+    fn event(&mut self, event: WaveEvent) -> Result<Option<State>> {
+        let mut res = None;
+        match event {
+            WaveEvent::Timestamp => self.previous_state.clone_from(&self.current_state),
+            WaveEvent::Scalar(code, value) => {
+                if code == self.clock {
                     match value {
-                        vcd::Value::V1 if clk_state == vcd::Value::V0 => {
-                            states.push(previous_state.clone());
-                            clk_state = vcd::Value::V1;
-                            started = true;
+                        vcd::Value::V1 if self.clk_state == vcd::Value::V0 => {
+                            res = Some(self.previous_state.clone());
+                            self.n_cycles += 1;
+                            self.clk_state = vcd::Value::V1;
+                            self.started = true;
                         }
                         vcd::Value::V0 | vcd::Value::V1 => {
-                            clk_state = value;
-                            started = true;
+                            self.clk_state = value;
+                            self.started = true;
                         }
                         vcd::Value::X | vcd::Value::Z => {
-                            if started {
+                            if self.started {
                                 bail!(
                                     "Invalid value for the clock: {:?} (at cycle >= {}).",
                                     value,
-                                    states.len()
+                                    self.n_cycles
                                 );
                             }
                         }
                     }
                 }
-                if let Some(var_id) = get_var_id(id_code) {
-                    current_state[var_id] = VarState::Scalar(value);
+                if let Some(var_id) = self.var_ids.get(&code) {
+                    self.current_state[*var_id] = VarState::Scalar(value);
                 }
             }
-            vcd::Command::ChangeVector(id_code, value) => {
-                if let Some(var_id) = get_var_id(id_code) {
-                    current_state[var_id] =
-                        VarState::Vector(pad_vec_and_reverse(value, used_vars[var_id].size));
+            WaveEvent::Vector(code, value) => {
+                if let Some(var_id) = self.var_ids.get(&code) {
+                    self.current_state[*var_id] =
+                        VarState::Vector(pad_vec_and_reverse(value, self.used_vars[*var_id].size));
                 }
             }
-            vcd::Command::Timestamp(_) => {
-                previous_state = current_state.clone();
-            }
-            _ => {}
         }
+        Ok(res)
     }
-    states.push(current_state);
-    Ok(states)
+}
+
+/// Number of events sent at once by the fst reader thread.
+const FST_BATCH_SIZE: usize = 4096;
+
+/// Events of the given signals of a fst file.
+/// The fst reader only provides a callback interface, hence it runs in its own thread, and sends
+/// the events through a bounded channel.
+fn fst_events<R: BufRead + Seek + Send + 'static>(
+    mut reader: fst_reader::FstReader<R>,
+    handles: Vec<fst_reader::FstSignalHandle>,
+) -> impl Iterator<Item = Result<WaveEvent>> {
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<Result<Vec<WaveEvent>>>(4);
+    std::thread::spawn(move || {
+        let mut batch = Vec::with_capacity(FST_BATCH_SIZE);
+        let mut time = None;
+        let mut res = Ok(());
+        let read_res = reader.read_signals(
+            &fst_reader::FstFilter::filter_signals(handles),
+            |t, handle, value| {
+                if res.is_err() {
+                    return;
+                }
+                if time != Some(t) {
+                    batch.push(WaveEvent::Timestamp);
+                    time = Some(t);
+                }
+                match fst_event(handle.get_index() as VarCode, value) {
+                    Ok(event) => batch.push(event),
+                    Err(error) => res = Err(error),
+                }
+                if batch.len() >= FST_BATCH_SIZE {
+                    let batch = std::mem::replace(&mut batch, Vec::with_capacity(FST_BATCH_SIZE));
+                    // If the receiver is gone, the events are not needed anymore.
+                    if sender.send(Ok(batch)).is_err() {
+                        res = Err(anyhow!("Receiver disconnected."));
+                    }
+                }
+            },
+        );
+        let _ = sender.send(Ok(batch));
+        if let Err(error) = res.and(read_res.map_err(Into::into)) {
+            let _ = sender.send(Err(error));
+        }
+    });
+    receiver.into_iter().flat_map(|batch| match batch {
+        Ok(events) => Either::Left(events.into_iter().map(Ok)),
+        Err(error) => Either::Right(std::iter::once(Err(error))),
+    })
+}
+
+fn fst_event(code: VarCode, value: fst_reader::FstSignalValue) -> Result<WaveEvent> {
+    let fst_reader::FstSignalValue::String(value) = value else {
+        bail!("Real variables are not supported.");
+    };
+    let value = value
+        .iter()
+        .map(|c| fst_value(*c))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(match value.as_slice() {
+        [] => bail!("Empty value in fst file."),
+        // Vector values are never truncated in fst files.
+        [value] => WaveEvent::Scalar(code, *value),
+        _ => WaveEvent::Vector(code, value),
+    })
+}
+
+/// Map a nine-valued logic value of a fst file to a vcd value.
+fn fst_value(value: u8) -> Result<vcd::Value> {
+    Ok(match value {
+        b'0' | b'l' | b'L' => vcd::Value::V0,
+        b'1' | b'h' | b'H' => vcd::Value::V1,
+        b'x' | b'X' | b'u' | b'U' | b'w' | b'W' | b'-' => vcd::Value::X,
+        b'z' | b'Z' => vcd::Value::Z,
+        _ => bail!("Invalid value '{}' in fst file.", value as char),
+    })
 }
@@ -8,9 +8,20 @@ pub struct Config {
     #[arg(long)]
     /// Path to synthesized json file from Yosys.
     pub json: String,
+    #[arg(long, required_unless_present = "stimulus")]
+    /// Path to simulation vcd (or fst) file.
+    pub vcd: Option<String>,
+    #[arg(long, conflicts_with = "vcd")]
+    /// Stimulus file (TOML) giving the values of the top-level signals, used instead of a
+    /// simulation vcd.
+    pub stimulus: Option<String>,
     #[arg(long)]
-    /// Path to simulation vcd file.
-    pub vcd: String,
+    /// Parse the simulation waveform as fst (default if the path ends with '.fst').
+    pub fst: bool,
+    #[arg(long, conflicts_with = "stimulus")]
+    /// Compare the values of the wires in the simulation of the netlist with the vcd, and stop at
+    /// the first mismatches.
+    pub cross_check: bool,
     #[arg(long)]
     /// Path to the output symbolic execution vcd.
     pub output_vcd: Option<String>,
@@ -20,15 +31,42 @@ pub struct Config {
     #[arg(long)]
     /// Main gadget module name.
     pub gname: String,
-    #[arg(long)]
+    #[arg(long, required_unless_present = "stimulus")]
     /// Name of the DUT instance in the testbench.
-    pub dut: String,
+    pub dut: Option<String>,
+    #[arg(long)]
+    /// Cell library (TOML file) describing additional cells of the netlist.
+    pub cell_lib: Option<String>,
+    #[arg(long)]
+    /// Annotations file (TOML, or JSON if the name ends with '.json'), whose annotations override
+    /// the attributes of the netlist.
+    pub annotations: Option<String>,
     #[arg(long)]
     /// Do not check for the presence of remaining secrets after the execution.
     pub no_check_state_cleared: bool,
     #[arg(long)]
     /// Do not check transition leakage.
     pub no_check_transitions: bool,
+    #[arg(long)]
+    /// Check glitch-sensitivity of the output shares, not only their sensitivity (e.g., for
+    /// circuits with registered outputs).
+    pub check_output_glitches: bool,
+    #[arg(long)]
+    /// DFFs and latches do not stop glitches, unless annotated with the 'matchi_stop_glitches'
+    /// attribute (on the cell or on its parent module).
+    pub no_dff_stop_glitches: bool,
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    /// Probing order of the verification (default: number of shares minus one). Below that,
+    /// wires and gates may be sensitive for multiple shares, as long as that many glitch-extended
+    /// probes cannot jointly observe all the shares of a sharing.
+    pub order: Option<u32>,
+    #[arg(long)]
+    /// Continue the simulation after a violation is found, and report all the violations at the
+    /// end.
+    pub keep_going: bool,
+    #[arg(long)]
+    /// Write a JSON report of all the violations to this file (implies --keep-going).
+    pub report_json: Option<String>,
 }
 
 pub fn config() -> &'static Config {
@@ -0,0 +1,179 @@
+//! Cross-check of the value simulation against the simulation vcd (--cross-check).
+//!
+//! Each wire of the netlist that is also in the vcd (under the DUT path) is compared at each
+//! cycle. Only the bits that are known ('0' or '1') in both simulations are compared.
+use super::clk_vcd::{VarOffsetId, VcdParsedHeader, VcdParsedStates};
+use super::module::{InstanceId, InstanceType, WireId};
+use super::netlist::{ModList, Netlist};
+use super::recsim::ModuleState;
+use super::ModuleId;
+use super::WireValue;
+use anyhow::{bail, Result};
+use std::fmt::Write;
+use yosys_netlist_json as yosys;
+
+/// Maximum number of mismatches reported.
+const MAX_REPORTED_MISMATCHES: usize = 10;
+
+#[derive(Debug, Clone)]
+struct CheckedWire {
+    /// Path in the vcd, and bit index.
+    path: Vec<String>,
+    offset: usize,
+    wire_id: WireId,
+    var: VarOffsetId,
+}
+
+/// Wires of a module instance that are checked.
+#[derive(Debug, Clone)]
+struct CheckedScope {
+    wires: Vec<CheckedWire>,
+    cells: Vec<(InstanceId, CheckedScope)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CrossCheck {
+    scope: CheckedScope,
+}
+
+impl CrossCheck {
+    pub fn new<R: std::io::BufRead + std::io::Seek>(
+        vcd_header: &mut VcdParsedHeader<R>,
+        dut_path: &[String],
+        module_id: ModuleId,
+        netlist: &Netlist,
+        yosys_netlist: &yosys::Netlist,
+    ) -> Self {
+        let scope = CheckedScope::new(vcd_header, dut_path, module_id, netlist, yosys_netlist);
+        Self { scope }
+    }
+    /// Number of checked wires (bits).
+    pub fn n_wires(&self) -> usize {
+        self.scope.n_wires()
+    }
+    /// Check the state of the simulation at the given cycle against the vcd.
+    pub fn check(
+        &self,
+        state: &ModuleState,
+        vcd_states: &VcdParsedStates,
+        cycle: usize,
+    ) -> Result<()> {
+        let mut mismatches = vec![];
+        self.scope
+            .mismatches(state, vcd_states, cycle, &mut mismatches);
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        let mut msg = format!(
+            "The simulation of the netlist does not match the vcd at cycle {} ({} mismatches):",
+            cycle,
+            mismatches.len()
+        );
+        for (wire, vcd_value, value) in mismatches.iter().take(MAX_REPORTED_MISMATCHES) {
+            write!(
+                msg,
+                "\n\t{}[{}] is {} in the vcd, but {} in the simulation",
+                wire.path.join("."),
+                wire.offset,
+                u8::from(bool::from(*vcd_value)),
+                u8::from(bool::from(*value))
+            )
+            .unwrap();
+        }
+        if mismatches.len() > MAX_REPORTED_MISMATCHES {
+            write!(msg, "\n\t...").unwrap();
+        }
+        bail!(msg);
+    }
+}
+
+impl CheckedScope {
+    fn new<R: std::io::BufRead + std::io::Seek>(
+        vcd_header: &mut VcdParsedHeader<R>,
+        path: &[String],
+        module_id: ModuleId,
+        netlist: &Netlist,
+        yosys_netlist: &yosys::Netlist,
+    ) -> Self {
+        let module = netlist.module(module_id);
+        let yosys_module = &yosys_netlist.modules[&module.name];
+        let mut wires = vec![];
+        for (name, net) in yosys_module.netnames.iter() {
+            // The value of the clock is not simulated.
+            if module
+                .clock
+                .as_ref()
+                .is_some_and(|clock| clock.name() == name)
+            {
+                continue;
+            }
+            // Names of flattened wires contain the path of their instance.
+            let wire_path = path
+                .iter()
+                .cloned()
+                .chain(name.split('.').map(ToOwned::to_owned))
+                .collect::<Vec<_>>();
+            for (offset, bitval) in net.bits.iter().enumerate() {
+                let (Ok(wire_id), Ok(var)) = (
+                    WireId::try_from(*bitval),
+                    vcd_header.add_var_offset(&wire_path, offset),
+                ) else {
+                    continue;
+                };
+                wires.push(CheckedWire {
+                    path: wire_path.clone(),
+                    offset,
+                    wire_id,
+                    var,
+                });
+            }
+        }
+        let cells = module
+            .instances
+            .iter_enumerated()
+            .filter_map(|(instance_id, instance)| {
+                let InstanceType::Module(submodule) = instance.architecture else {
+                    return None;
+                };
+                let mut sub_path = path.to_owned();
+                sub_path.push(instance.name.clone());
+                let scope = Self::new(vcd_header, &sub_path, submodule, netlist, yosys_netlist);
+                Some((instance_id, scope))
+            })
+            .collect();
+        Self { wires, cells }
+    }
+    fn n_wires(&self) -> usize {
+        self.wires.len()
+            + self
+                .cells
+                .iter()
+                .map(|(_, scope)| scope.n_wires())
+                .sum::<usize>()
+    }
+    fn mismatches<'a>(
+        &'a self,
+        state: &ModuleState,
+        vcd_states: &VcdParsedStates,
+        cycle: usize,
+        mismatches: &mut Vec<(&'a CheckedWire, WireValue, WireValue)>,
+    ) {
+        for wire in &self.wires {
+            let value = state.wire_states[wire.wire_id]
+                .as_ref()
+                .and_then(|wire_state| wire_state.value);
+            let vcd_value = vcd_states.get_var_offset(wire.var, cycle);
+            if let (Some(value), Some(vcd_value)) = (value, vcd_value) {
+                if value != vcd_value {
+                    mismatches.push((wire, vcd_value, value));
+                }
+            }
+        }
+        for (instance_id, scope) in &self.cells {
+            if let Some(instance_state) = state.instance_states[*instance_id].as_ref() {
+                let instance_state = instance_state.module_any().unwrap();
+                scope.mismatches(instance_state, vcd_states, cycle, mismatches);
+            }
+        }
+    }
+}
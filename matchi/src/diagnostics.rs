@@ -0,0 +1,253 @@
+//! Security violations found by the simulation, and their reporting.
+
+use crate::recsim::GlobInstId;
+use crate::share_set::ShareSet;
+use crate::top_sim::GlobSimCycle;
+use anyhow::Result;
+use indexmap::IndexMap;
+use std::fmt::Write;
+
+/// Kind of security violation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    /// Inputs of a gate are sensitive for multiple shares (glitch leakage).
+    GateMultiShare,
+    /// Inputs of a gate are sensitive for multiple shares over consecutive cycles (transition
+    /// leakage).
+    GateTransition,
+    /// A wire is (glitch-)sensitive for multiple shares.
+    WireMultiShare,
+    /// A wire annotated as glitch-free may glitch.
+    GlitchFree,
+    /// A share input of a pipeline gadget is sensitive for other shares.
+    GadgetInputShare,
+    /// A randomness input of a pipeline gadget is sensitive.
+    GadgetInputRandom,
+    /// A control input of a gadget, or a control output, is not deterministic.
+    ControlNotDeterministic,
+    /// No pipeline bubble between dependent executions of a PINI gadget.
+    GadgetTransition,
+    /// A randomness input of a gadget is not fresh.
+    RandomnessNotFresh,
+    /// A random input is used in multiple places.
+    RandomnessReuse,
+    /// An output share is sensitive for other shares, or when it is not valid.
+    OutputPort,
+    /// A secret remains in the state at the end of the execution.
+    StateNotCleared,
+    /// A 'matchi_active' signal has a different value in the simulation of the netlist and in
+    /// the vcd (--cross-check).
+    ActiveMismatch,
+}
+
+/// A security violation. This is used as error type, such that it can be either reported
+/// immediately, or collected.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    /// Instance where the violation occurs.
+    pub inst: Option<GlobInstId>,
+    /// Instance path, if inst is not known.
+    pub path: Option<String>,
+    pub wire: Option<String>,
+    /// Share sets involved (e.g., of each input of a gate).
+    pub shares: Vec<ShareSet>,
+    msg: String,
+}
+
+impl Violation {
+    pub fn new(kind: ViolationKind, msg: impl Into<String>) -> Self {
+        Self {
+            kind,
+            inst: None,
+            path: None,
+            wire: None,
+            shares: vec![],
+            msg: msg.into(),
+        }
+    }
+    pub fn at_inst(mut self, inst: GlobInstId) -> Self {
+        self.inst = Some(inst);
+        self
+    }
+    pub fn at_path(mut self, path: String) -> Self {
+        self.path = Some(path);
+        self
+    }
+    pub fn on_wire(mut self, wire: impl ToString) -> Self {
+        self.wire = Some(wire.to_string());
+        self
+    }
+    pub fn with_shares(mut self, shares: impl IntoIterator<Item = ShareSet>) -> Self {
+        self.shares = shares.into_iter().collect();
+        self
+    }
+    /// Add the share sets of the other probes that, jointly with this one, may observe all the
+    /// shares (at a reduced probing order).
+    pub fn with_joint_probes(mut self, probes: Vec<ShareSet>) -> Self {
+        if !probes.is_empty() {
+            write!(
+                self.msg,
+                "\nJointly with probes observing shares {}.",
+                probes
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .unwrap();
+            self.shares.extend(probes);
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for Violation {}
+
+/// A violation found at a given cycle of the simulation.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub cycle: GlobSimCycle,
+    /// Instance path (None for the simulated gadget itself).
+    pub path: Option<String>,
+    pub violation: Violation,
+    /// Message of the violation, with its context (e.g., the checked instance).
+    pub message: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ViolationRecord {
+    kind: ViolationKind,
+    /// Name of the verified gadget.
+    gadget: String,
+    cycle: usize,
+    path: Option<String>,
+    wire: Option<String>,
+    shares: Vec<Vec<u32>>,
+    message: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Summary {
+    passed: bool,
+    n_violations: usize,
+    /// Error that stopped the verification.
+    error: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Report<'a> {
+    violations: &'a [ViolationRecord],
+    summary: Summary,
+}
+
+/// Collector for all the violations found during the verification.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    violations: Vec<ViolationRecord>,
+}
+
+impl Diagnostics {
+    pub fn add(&mut self, gadget: &str, diagnostic: Diagnostic) {
+        let violation = diagnostic.violation;
+        self.violations.push(ViolationRecord {
+            kind: violation.kind,
+            gadget: gadget.to_owned(),
+            cycle: diagnostic.cycle.index(),
+            path: diagnostic.path,
+            wire: violation.wire,
+            shares: violation
+                .shares
+                .iter()
+                .map(|shares| shares.iter().map(|share| share.raw()).collect())
+                .collect(),
+            message: diagnostic.message,
+        });
+    }
+    pub fn len(&self) -> usize {
+        self.violations.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.violations.is_empty()
+    }
+    /// Human-readable summary of the violations, where the violations of the same kind, at the
+    /// same place and for the same shares are merged across cycles.
+    pub fn summary(&self) -> String {
+        let mut groups: IndexMap<_, (Vec<usize>, &str)> = IndexMap::new();
+        for violation in &self.violations {
+            groups
+                .entry((
+                    &violation.gadget,
+                    violation.kind,
+                    &violation.path,
+                    &violation.wire,
+                    &violation.shares,
+                ))
+                .or_insert_with(|| (vec![], &violation.message))
+                .0
+                .push(violation.cycle);
+        }
+        let mut res = String::new();
+        for ((gadget, _, path, wire, _), (cycles, message)) in groups {
+            write!(res, "Violation in {}", gadget).unwrap();
+            if let Some(path) = path {
+                write!(res, ".{}", path).unwrap();
+            }
+            if let Some(wire) = wire {
+                write!(res, ", wire {}", wire).unwrap();
+            }
+            writeln!(
+                res,
+                " at cycle{} {}:\n\t{}",
+                if cycles.len() > 1 { "s" } else { "" },
+                cycle_ranges(&cycles),
+                message.replace('\n', "\n\t")
+            )
+            .unwrap();
+        }
+        res
+    }
+    /// Write the JSON report, error is the error that stopped the verification (if any).
+    pub fn write_json(&self, fname: &str, error: Option<&anyhow::Error>) -> Result<()> {
+        let report = Report {
+            violations: &self.violations,
+            summary: Summary {
+                passed: self.violations.is_empty() && error.is_none(),
+                n_violations: self.violations.len(),
+                error: error.map(|error| format!("{:#}", error)),
+            },
+        };
+        let file = std::io::BufWriter::new(std::fs::File::create(fname)?);
+        serde_json::to_writer_pretty(file, &report)?;
+        Ok(())
+    }
+}
+
+/// Format a sorted list of cycles as ranges (e.g., "2, 5, 12–340").
+fn cycle_ranges(cycles: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for cycle in cycles.iter().copied() {
+        match ranges.last_mut() {
+            Some((_, end)) if cycle <= *end + 1 => *end = cycle,
+            _ => ranges.push((cycle, cycle)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}\u{2013}{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -10,7 +10,7 @@ pub mod top;
 mod yosys_ext;
 
 pub use pipeline::PipelineGadget;
-pub use top::TopGadget;
+pub use top::{LoopyGadget, TopGadget};
 
 // Time unit, in clock cycles
 new_id!(Latency, LatencyVec, LatencySlice);
@@ -42,10 +42,16 @@ pub enum PortRole {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum GadgetProp {
     Pini,
-    // TODO: handle O-PINI gadgets.
     Opini,
 }
 impl GadgetProp {
+    /// Output shares are considered as (glitch-extended) probes.
+    pub fn has_output_probes(&self) -> bool {
+        match self {
+            GadgetProp::Pini => false,
+            GadgetProp::Opini => true,
+        }
+    }
     pub fn requires_bubble(&self) -> bool {
         match self {
             GadgetProp::Pini => true,
@@ -63,6 +69,12 @@ pub enum GadgetStrat {
     Isolate,
     DeepVerif,
 }
+impl GadgetStrat {
+    /// The gadget is verified on its own, then used as a black box.
+    pub fn is_verified_alone(&self) -> bool {
+        matches!(self, GadgetStrat::CompositePipeline | GadgetStrat::Isolate)
+    }
+}
 
 //TODO: when checking transitions: check that all gadgets are pipeline.
 
@@ -90,7 +102,8 @@ impl TryFrom<&str> for GadgetStrat {
         Ok(match value {
             "assumed" => Self::Assumed,
             "composite_top" => Self::CompositeTop,
-            //"isolate" => Self::Isolate,
+            "composite_pipeline" => Self::CompositePipeline,
+            "isolate" => Self::Isolate,
             //"deep_verif" => Self::DeepVerif,
             _ => bail!("{value} is not a known verification strategy."),
         })
@@ -1,11 +1,14 @@
-use super::{Latency, PortRole, RndPortVec};
-use crate::module::{self, ConnectionVec, InputId, InputVec, OutputVec};
+use super::{GadgetStrat, Latency, PortRole, RndPortVec, Slatency};
+use crate::module::{
+    self, ConnectionVec, InputId, InputVec, InstanceId, InstanceType, InstanceVec, OutputVec,
+    WireId, WireVec,
+};
 use crate::netlist::{ModList, Netlist};
 use crate::share_set::ShareId;
 use crate::ModuleId;
 
 use super::yosys_ext;
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 
 use yosys_netlist_json as yosys;
 
@@ -43,8 +46,15 @@ impl PipelineGadget {
             return Ok(None);
         };
         if builder.gadget_attrs.arch != super::GadgetArch::Pipeline {
+            // Loopy 'isolate' gadgets are handled by LoopyGadget.
             return Ok(None);
         }
+        if !matches!(
+            builder.gadget_attrs.strat,
+            GadgetStrat::Assumed | GadgetStrat::CompositePipeline | GadgetStrat::Isolate
+        ) {
+            bail!("Pipeline gadgets must have 'assumed', 'composite_pipeline' or 'isolate' verification strategy.");
+        }
         let input_roles = builder.input_roles()?;
         let output_share_id = builder
             .output_roles()?
@@ -99,4 +109,176 @@ impl PipelineGadget {
         let module = netlist.module(self.module_id);
         self.max_input_latency - self.latency[module.input_ports[input]]
     }
+    /// Check that the gadget has a strict pipeline structure that matches its latency
+    /// annotations: every wire belongs to a single pipeline stage.
+    pub fn check_pipeline_structure(&self, netlist: &Netlist) -> Result<()> {
+        let module = netlist.module(self.module_id);
+        let input_lats = module
+            .input_ports
+            .iter()
+            .map(|con_id| Some(self.latency[*con_id]))
+            .collect();
+        let output_lats =
+            LatencyAnalysis::new(self.module_id, input_lats, netlist).output_lats()?;
+        for (con_id, lat) in std::iter::zip(&module.output_ports, output_lats) {
+            if lat.is_some_and(|lat| lat != self.latency[*con_id]) {
+                bail!(
+                    "Output {} is computed at latency {}, but it is annotated with latency {}.",
+                    module.ports[*con_id],
+                    lat.unwrap(),
+                    self.latency[*con_id]
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum WireLat {
+    Unknown,
+    InProgress,
+    /// None for wires that do not depend on any input (e.g., constants).
+    Done(Option<Latency>),
+}
+
+/// Pipeline stage of each wire of a module, given the stage of its inputs.
+struct LatencyAnalysis<'a> {
+    module_id: ModuleId,
+    netlist: &'a Netlist,
+    input_lats: InputVec<Option<Latency>>,
+    wire_lats: WireVec<WireLat>,
+    instance_lats: InstanceVec<Option<OutputVec<Option<Latency>>>>,
+}
+
+impl<'a> LatencyAnalysis<'a> {
+    fn new(
+        module_id: ModuleId,
+        input_lats: InputVec<Option<Latency>>,
+        netlist: &'a Netlist,
+    ) -> Self {
+        let module = netlist.module(module_id);
+        Self {
+            module_id,
+            netlist,
+            input_lats,
+            wire_lats: WireVec::from_vec(vec![WireLat::Unknown; module.wires.len()]),
+            instance_lats: InstanceVec::from_vec(vec![None; module.instances.len()]),
+        }
+    }
+    fn output_lats(&mut self) -> Result<OutputVec<Option<Latency>>> {
+        let module = self.netlist.module(self.module_id);
+        module
+            .output_ports
+            .iter()
+            .map(|con_id| self.wire_lat(module.connection_wires[*con_id]))
+            .collect::<Result<_>>()
+            .with_context(|| format!("In module {}.", module.name))
+    }
+    fn wire_lat(&mut self, wire: WireId) -> Result<Option<Latency>> {
+        match self.wire_lats[wire] {
+            WireLat::Done(lat) => return Ok(lat),
+            WireLat::InProgress => {
+                bail!(
+                    "Wire {} is in a loop, this is not a pipeline.",
+                    self.netlist.module(self.module_id).wire_name(wire)
+                );
+            }
+            WireLat::Unknown => {}
+        }
+        self.wire_lats[wire] = WireLat::InProgress;
+        let (instance_id, output_id) = self.netlist.module(self.module_id).wires[wire].source;
+        let lat = self.instance_lats(instance_id)?[output_id];
+        self.wire_lats[wire] = WireLat::Done(lat);
+        Ok(lat)
+    }
+    fn instance_lats(&mut self, instance_id: InstanceId) -> Result<&OutputVec<Option<Latency>>> {
+        if self.instance_lats[instance_id].is_none() {
+            let lats = self.compute_instance_lats(instance_id)?;
+            self.instance_lats[instance_id] = Some(lats);
+        }
+        Ok(self.instance_lats[instance_id].as_ref().unwrap())
+    }
+    fn compute_instance_lats(
+        &mut self,
+        instance_id: InstanceId,
+    ) -> Result<OutputVec<Option<Latency>>> {
+        let netlist = self.netlist;
+        let instance = &netlist.module(self.module_id).instances[instance_id];
+        let mut input_lats = |inputs: &[module::ConnectionId]| -> Result<Vec<Option<Latency>>> {
+            inputs
+                .iter()
+                .map(|con_id| self.wire_lat(instance.connections[*con_id]))
+                .collect()
+        };
+        Ok(match &instance.architecture {
+            InstanceType::Input(input_id, _) => {
+                OutputVec::from_vec(vec![self.input_lats[*input_id]])
+            }
+            InstanceType::Tie(_) | InstanceType::Clock => OutputVec::from_vec(vec![None]),
+            InstanceType::Gate(gate) => {
+                let lat = same_lat(input_lats(gate.input_ports().as_raw_slice())?)
+                    .with_context(|| format!("Inputs of gate {}.", instance.name))?;
+                // Sequential gates start a new pipeline stage.
+                let lat = lat.map(|lat| if gate.clock().is_some() { lat + 1 } else { lat });
+                OutputVec::from_vec(vec![lat; gate.output_ports().len()])
+            }
+            InstanceType::Module(submodule_id) => {
+                let submodule = netlist.module(*submodule_id);
+                let sub_input_lats = input_lats(submodule.input_ports.as_raw_slice())?;
+                if let Some(gadget) = netlist.gadget(*submodule_id) {
+                    // Cycle at which the execution of the sub-gadget starts.
+                    let starts = std::iter::zip(&submodule.input_ports, sub_input_lats)
+                        .map(|(con_id, lat)| {
+                            lat.map(|lat| {
+                                Slatency::from(lat) - Slatency::from(gadget.latency[*con_id])
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    let start = same_lat(starts).with_context(|| {
+                        format!(
+                            "Inputs of gadget {} do not match its latency annotations.",
+                            instance.name
+                        )
+                    })?;
+                    if start.is_some_and(|start| start < 0) {
+                        bail!("Gadget {} has inputs at a latency smaller than their annotated latency.", instance.name);
+                    }
+                    submodule
+                        .output_ports
+                        .iter()
+                        .map(|con_id| start.map(|start| gadget.latency[*con_id] + start))
+                        .collect()
+                } else {
+                    LatencyAnalysis::new(
+                        *submodule_id,
+                        sub_input_lats.into_iter().collect(),
+                        netlist,
+                    )
+                    .output_lats()
+                    .with_context(|| format!("In instance {}.", instance.name))?
+                }
+            }
+        })
+    }
+}
+
+/// Common latency of a set of wires (ignoring wires that do not depend on any input).
+fn same_lat<T: Eq + Copy + std::fmt::Display>(
+    lats: impl IntoIterator<Item = Option<T>>,
+) -> Result<Option<T>> {
+    let mut res = None;
+    for lat in lats.into_iter().flatten() {
+        match res {
+            Some(res) if res != lat => {
+                bail!(
+                    "Wires from different pipeline stages ({} and {}) are mixed.",
+                    res,
+                    lat
+                );
+            }
+            _ => res = Some(lat),
+        }
+    }
+    Ok(res)
 }
@@ -1,11 +1,11 @@
-use super::{GadgetArch, GadgetProp, GadgetStrat, Latency, PortRole, RndPortVec};
-use crate::module::{self, ConnectionVec, InputId, WireName};
+use super::{GadgetArch, GadgetProp, GadgetStrat, Latency, PipelineGadget, PortRole, RndPortVec};
+use crate::module::{self, ConnectionVec, InputId, WireId, WireName};
 use crate::type_utils::new_id;
 use crate::ModuleId;
 use fnv::FnvHashMap as HashMap;
 
 use super::yosys_ext;
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 use yosys_netlist_json as yosys;
 
@@ -18,6 +18,8 @@ new_id!(ActiveWireId, ActiveWireVec, ActiveWireSlice);
 #[derive(Clone, Debug)]
 pub struct TopGadget {
     pub module_id: ModuleId,
+    /// Security property.
+    pub prop: GadgetProp,
     /// Roles of the input wires. Output wires are all shares.
     pub port_roles: ConnectionVec<PortRole>,
     /// Latency associated to each connection wire (including control, excluding clock).
@@ -32,8 +34,8 @@ pub struct TopGadget {
     pub active_wires: ActiveWireVec<WireName>,
 }
 
-/// TODO: this should be a ref to a signal found in the top-level simulation (or can it be a
-/// "control" signal ?).
+/// Signal of the top-level module, whose value is taken from the simulation of the netlist (or
+/// from the stimulus, for input ports and signals that are not in the netlist).
 #[derive(Debug, Clone)]
 pub struct SimSignal(pub ActiveWireId);
 
@@ -99,6 +101,19 @@ impl TopGadget {
                 module.name
             );
         };
+        if builder.gadget_attrs.strat != GadgetStrat::CompositeTop {
+            bail!("Top-level gadget must have 'composite_top' verification strategy.");
+        }
+        if builder.gadget_attrs.arch != GadgetArch::Loopy {
+            bail!("Cannot verify 'pipeline' top-level gadgets.");
+        }
+        Self::from_builder(module, yosys_module, builder)
+    }
+    fn from_builder(
+        module: &module::Module,
+        yosys_module: &yosys::Module,
+        builder: super::gadget_builder::GadgetBuilder<'_>,
+    ) -> Result<Self> {
         let port_roles = builder.port_roles()?;
         builder.check_clock(&module.clock)?;
         let mut rnd_ports = module
@@ -145,17 +160,9 @@ impl TopGadget {
                 Ok(cond)
             })
             .collect::<Result<ConnectionVec<_>>>()?;
-        if builder.gadget_attrs.strat != GadgetStrat::CompositeTop {
-            bail!("Top-level gadget must have 'composite_top' verification strategy.");
-        }
-        if builder.gadget_attrs.arch != GadgetArch::Loopy {
-            bail!("Cannot verify 'pipeline' top-level gadgets.");
-        }
-        if builder.gadget_attrs.prop != GadgetProp::Pini {
-            bail!("Cannot verify non-'PINI' top-level gadgets.");
-        }
         Ok(Self {
             module_id: module.id,
+            prop: builder.gadget_attrs.prop,
             port_roles,
             latency,
             rnd_ports,
@@ -164,4 +171,91 @@ impl TopGadget {
             active_wires: awbuilder.active_wires,
         })
     }
+    /// Top-level gadget view of a pipeline gadget, used to verify it in isolation.
+    /// Share and random ports are active only at their latency, with the execution starting at
+    /// cycle 0.
+    pub fn from_pipeline(gadget: &PipelineGadget, module: &module::Module) -> Self {
+        let mut port_roles = ConnectionVec::from_vec(vec![PortRole::Control; module.ports.len()]);
+        for (input_id, con_id) in module.input_ports.iter_enumerated() {
+            port_roles[*con_id] = gadget.input_roles[input_id].clone();
+        }
+        for (output_id, con_id) in module.output_ports.iter_enumerated() {
+            port_roles[*con_id] = PortRole::Share(gadget.output_share_id[output_id]);
+        }
+        let latency = std::iter::zip(&gadget.latency, &port_roles)
+            .map(|(lat, port_role)| match port_role {
+                PortRole::Share(_) | PortRole::Random(_) => {
+                    Some(LatencyCondition::Lats(vec![*lat]))
+                }
+                PortRole::Control => None,
+            })
+            .collect();
+        Self {
+            module_id: gadget.module_id,
+            prop: gadget.prop.clone(),
+            port_roles,
+            latency,
+            nshares: gadget.nshares,
+            rnd_ports: gadget.rnd_ports.clone(),
+            exec_active: None,
+            active_wires: ActiveWireVec::new(),
+        }
+    }
+}
+
+impl LatencyCondition {
+    /// The connection is valid, exec_cycle being the number of cycles since the start of the
+    /// last execution.
+    pub fn is_valid(&self, exec_cycle: Option<usize>, active: &ActiveWireVec<bool>) -> bool {
+        match self {
+            LatencyCondition::Always => true,
+            LatencyCondition::Never => false,
+            LatencyCondition::Lats(lats) => exec_cycle
+                .is_some_and(|cycle| lats.binary_search(&Latency::from_usize(cycle)).is_ok()),
+            LatencyCondition::OnActive(sim_signal) => active[sim_signal.0],
+        }
+    }
+}
+
+/// Loopy gadget with the 'isolate' verification strategy: it is verified on its own as a
+/// top-level gadget, then its instances are used as black boxes.
+#[derive(Clone, Debug)]
+pub struct LoopyGadget {
+    pub gadget: TopGadget,
+    /// Wire of the module for each 'matchi_active' signal.
+    pub active_wires: ActiveWireVec<WireId>,
+}
+
+impl LoopyGadget {
+    pub fn new(module: &module::Module, yosys_netlist: &yosys::Netlist) -> Result<Option<Self>> {
+        let yosys_module = &yosys_netlist.modules[&module.name];
+        let Some(builder) = super::gadget_builder::GadgetBuilder::new(module, yosys_module)? else {
+            return Ok(None);
+        };
+        if builder.gadget_attrs.strat != GadgetStrat::Isolate
+            || builder.gadget_attrs.arch != GadgetArch::Loopy
+        {
+            return Ok(None);
+        }
+        let gadget = TopGadget::from_builder(module, yosys_module, builder)?;
+        let active_wires = gadget
+            .active_wires
+            .iter()
+            .map(|wire_name| {
+                let bit = yosys_module
+                    .netnames
+                    .get(wire_name.name())
+                    .and_then(|netname| netname.bits.get(wire_name.offset))
+                    .ok_or_else(|| {
+                        anyhow!("Wire {} not found in module {}.", wire_name, module.name)
+                    })?;
+                WireId::try_from(*bit)
+            })
+            .collect::<Result<ActiveWireVec<_>>>()
+            .context("Error while looking up 'matchi_active' signals.")?;
+        Ok(Some(Self {
+            gadget,
+            active_wires,
+        }))
+    }
 }
@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 use std::fs::File;
 use std::io::BufReader;
@@ -11,13 +11,17 @@ extern crate log;
 mod config;
 #[macro_use]
 mod type_utils;
+mod annotations;
 mod clk_vcd;
+mod cross_check;
+mod diagnostics;
 mod gadget;
 mod module;
 mod netlist;
 mod recsim;
 mod share_set;
 mod simulation;
+mod stimulus;
 mod top_sim;
 mod vcd_writer;
 mod wire_value;
@@ -26,7 +30,8 @@ use wire_value::WireValue;
 
 new_id!(ModuleId, ModuleVec, ModuleSlice);
 
-use netlist::Netlist;
+use diagnostics::Diagnostics;
+use netlist::{ModList, Netlist};
 
 /// Return the path of a signal in a module, splitting the signal name if needed.
 fn signal_path(module: &[String], sig_name: &str) -> Vec<String> {
@@ -38,21 +43,54 @@ fn signal_path(module: &[String], sig_name: &str) -> Vec<String> {
 }
 
 /// Verify that the top-level gadets (and all sub-gadgets) satisfy the rules.
-fn check_gadget_top<'a>(netlist: &'a yosys::Netlist) -> Result<()> {
+/// If diagnostics is given, violations are collected in it instead of stopping the verification.
+fn check_gadget_top<'a>(
+    netlist: &'a yosys::Netlist,
+    mut diagnostics: Option<&mut Diagnostics>,
+) -> Result<()> {
     println!("building netlist...");
     let gadget_name = config::config().gname.as_str();
-    let netlist_sim = Netlist::new(netlist, gadget_name)?;
-    let dut_path = signal_path(&[], config::config().dut.as_str());
+    let cell_lib = config::config()
+        .cell_lib
+        .as_deref()
+        .map(module::CellLib::from_file)
+        .transpose()?
+        .unwrap_or_default();
+    let netlist_sim = Netlist::new(netlist, gadget_name, &cell_lib)?;
+    if let Some(order) = config::config().order {
+        let nshares = netlist_sim.top_gadget.nshares;
+        if order >= nshares {
+            bail!(
+                "The probing order ({}) must be lower than the number of shares ({}).",
+                order,
+                nshares
+            );
+        }
+    }
 
-    println!("initializing sim vcd states...");
-    let mut vcd_file = open_simu_vcd()?;
-    let vcd_parser = vcd::Parser::new(&mut vcd_file);
-    // Simulation using recsim
-    println!("Starting simu");
-    let simulator = top_sim::Simulator::new(&netlist_sim, vcd_parser, &dut_path)?;
-    let n_cycles = simulator.n_cycles();
-    let mut sim_states_iter =
-        simulator.simu(&netlist_sim, top_sim::GlobSimCycle::from_usize(n_cycles));
+    check_composite_gadgets(&netlist_sim, netlist, diagnostics.as_deref_mut())?;
+
+    let mut simulator = if let Some(stimulus) = config::config().stimulus.as_deref() {
+        println!("parsing stimulus...");
+        let spec = stimulus::StimulusSpec::from_file(stimulus)?;
+        println!("Starting simu");
+        top_sim::Simulator::from_spec(&netlist_sim, netlist, spec)?
+    } else {
+        // Without stimulus file, --vcd and --dut are required by the command-line parser.
+        let vcd = config::config().vcd.as_deref().unwrap();
+        let dut_path = signal_path(&[], config::config().dut.as_deref().unwrap());
+        println!("initializing sim vcd states...");
+        let vcd_parser = open_simu_vcd(vcd)?;
+        // Simulation using recsim
+        println!("Starting simu");
+        top_sim::Simulator::new(
+            &netlist_sim,
+            &netlist_sim.top_gadget,
+            netlist,
+            vcd_parser,
+            &dut_path,
+        )?
+    };
     let mut vcd_writer = config::config()
         .output_vcd
         .as_ref()
@@ -66,8 +104,106 @@ fn check_gadget_top<'a>(netlist: &'a yosys::Netlist) -> Result<()> {
             )
         })
         .transpose()?;
+    run_simulation(
+        &mut simulator,
+        &netlist_sim,
+        vcd_writer.as_mut(),
+        diagnostics.as_deref_mut().map(|d| (d, gadget_name)),
+    )?;
+    if diagnostics.map_or(0, |d| d.len()) == 0 {
+        println!("Verification successful.");
+    }
+    Ok(())
+}
+
+/// Verify each gadget with the 'isolate' or 'composite_pipeline' strategy on its own, such that it
+/// can be used as a black box in the rest of the verification.
+fn check_composite_gadgets(
+    netlist_sim: &Netlist,
+    netlist: &yosys::Netlist,
+    mut diagnostics: Option<&mut Diagnostics>,
+) -> Result<()> {
+    for gadget in netlist_sim.gadgets() {
+        if !gadget.strat.is_verified_alone() {
+            continue;
+        }
+        let name = &netlist_sim.module(gadget.module_id).name;
+        println!("verifying gadget {}...", name);
+        // The instances of the gadget are black boxes with a pipeline behavior, which is only
+        // valid if the gadget has a strict pipeline structure.
+        gadget
+            .check_pipeline_structure(netlist_sim)
+            .with_context(|| format!("Gadget {} is not a valid pipeline.", name))?;
+        let mut simulator = top_sim::Simulator::new_isolated(netlist_sim, gadget.module_id)?;
+        run_simulation::<std::io::Sink>(
+            &mut simulator,
+            netlist_sim,
+            None,
+            diagnostics.as_deref_mut().map(|d| (d, name.as_str())),
+        )
+        .with_context(|| format!("Verification of gadget {} failed.", name))?;
+    }
+    // Loopy gadgets depend on their control inputs: each instance is verified with its own
+    // stimulus, taken from the simulation vcd.
+    for (module_id, loopy_gadget) in netlist_sim.loopy_gadgets() {
+        let name = &netlist_sim.module(module_id).name;
+        let (Some(vcd), Some(dut)) = (
+            config::config().vcd.as_deref(),
+            config::config().dut.as_deref(),
+        ) else {
+            bail!(
+                "Gadget {} has the 'isolate' strategy and a 'loopy' architecture, its verification requires a simulation vcd (--vcd and --dut).",
+                name
+            );
+        };
+        for inst_path in netlist_sim.instance_paths(module_id) {
+            let inst_name = format!("{}.{}", config::config().gname, inst_path.join("."));
+            println!("verifying gadget {} (instance {})...", name, inst_name);
+            let mut dut_path = signal_path(&[], dut);
+            dut_path.extend(inst_path);
+            let mut simulator = top_sim::Simulator::new(
+                netlist_sim,
+                &loopy_gadget.gadget,
+                netlist,
+                open_simu_vcd(vcd)?,
+                &dut_path,
+            )?;
+            run_simulation::<std::io::Sink>(
+                &mut simulator,
+                netlist_sim,
+                None,
+                diagnostics.as_deref_mut().map(|d| (d, inst_name.as_str())),
+            )
+            .with_context(|| {
+                format!(
+                    "Verification of gadget {} (instance {}) failed.",
+                    name, inst_name
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Run the simulation for all cycles, checking security at each cycle.
+/// If diagnostics is given, the violations are collected in it (for the given gadget name).
+fn run_simulation<W: std::io::Write>(
+    simulator: &mut top_sim::Simulator,
+    netlist_sim: &Netlist,
+    mut vcd_writer: Option<&mut vcd_writer::VcdWriter<W>>,
+    mut diagnostics: Option<(&mut Diagnostics, &str)>,
+) -> Result<()> {
+    let n_cycles = simulator.n_cycles();
+    let mut sim_states_iter = simulator.simu(netlist_sim);
+    if diagnostics.is_some() {
+        sim_states_iter = sim_states_iter.collecting_violations();
+    }
     for i in 0.. {
-        println!("Simu cycle {}/{}", i, n_cycles);
+        if let Some(n_cycles) = n_cycles {
+            println!("Simu cycle {}/{}", i, n_cycles);
+        } else {
+            println!("Simu cycle {}", i);
+        }
         let Some(iter) = sim_states_iter.next()? else {
             break;
         };
@@ -77,19 +213,30 @@ fn check_gadget_top<'a>(netlist: &'a yosys::Netlist) -> Result<()> {
             .map(|w| w.new_state(sim_states_iter.state()))
             .transpose()?;
         sim_states_iter.check()?;
+        if let Some((diagnostics, gadget_name)) = diagnostics.as_mut() {
+            for diagnostic in sim_states_iter.take_diagnostics() {
+                diagnostics.add(gadget_name, diagnostic);
+            }
+        }
     }
-    println!("Verification successful.");
     Ok(())
 }
 
-fn open_simu_vcd() -> Result<std::io::BufReader<std::fs::File>> {
-    let file_simu = File::open(&config::config().vcd).map_err(|_| {
+fn open_simu_vcd(path: &str) -> Result<clk_vcd::WaveformParser<BufReader<File>>> {
+    let file_simu = File::open(path).map_err(|_| {
         anyhow!(
             "Did not find the vcd file: '{}'.\nPlease check your testbench and simulator commands.",
-            &config::config().vcd
+            path
         )
     })?;
-    Ok(BufReader::new(file_simu))
+    let file_simu = BufReader::new(file_simu);
+    if config::config().fst || path.ends_with(".fst") {
+        let reader = fst_reader::FstReader::open(file_simu)
+            .with_context(|| format!("Could not parse fst file '{}'.", path))?;
+        Ok(clk_vcd::WaveformParser::Fst(Box::new(reader)))
+    } else {
+        Ok(clk_vcd::WaveformParser::Vcd(vcd::Parser::new(file_simu)))
+    }
 }
 
 pub fn main() -> Result<()> {
@@ -100,7 +247,28 @@ pub fn main() -> Result<()> {
         )
     })?;
     let file_synth = BufReader::new(file_synth);
-    let netlist = yosys::Netlist::from_reader(file_synth)?;
-    check_gadget_top(&netlist)?;
+    let mut netlist = yosys::Netlist::from_reader(file_synth)?;
+    if let Some(annotations) = config::config().annotations.as_deref() {
+        annotations::Annotations::from_file(annotations)?.apply(&mut netlist)?;
+    }
+    let report_fname = config::config().report_json.as_ref();
+    if !config::config().keep_going && report_fname.is_none() {
+        return check_gadget_top(&netlist, None);
+    }
+    let mut diagnostics = Diagnostics::default();
+    let res = check_gadget_top(&netlist, Some(&mut diagnostics));
+    if let Some(report_fname) = report_fname {
+        diagnostics
+            .write_json(report_fname, res.as_ref().err())
+            .with_context(|| format!("Failed to write report '{}'.", report_fname))?;
+    }
+    print!("{}", diagnostics.summary());
+    res?;
+    if !diagnostics.is_empty() {
+        bail!(
+            "Verification failed: {} violations found.",
+            diagnostics.len()
+        );
+    }
     Ok(())
 }
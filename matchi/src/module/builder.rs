@@ -1,11 +1,13 @@
+use super::cell_lib::CellLib;
 use super::gates::Gate;
+use super::rtl_cells::{self, NewWires};
 use super::yosys_ext;
 use super::{
     ConnectionSet, ConnectionVec, InputId, InputVec, Instance, InstanceId, InstanceType,
     InstanceVec, Module, ModuleCombDeps, OutputId, OutputVec, WireGraph, WireId, WireProperties,
     WireVec,
 };
-use crate::gadget::PipelineGadget;
+use crate::gadget::{LoopyGadget, PipelineGadget};
 use crate::netlist::ModList;
 use crate::{ModuleId, ModuleVec};
 use anyhow::{anyhow, bail, Context, Result};
@@ -19,6 +21,7 @@ pub struct ModListBuilder {
     pub names: HashMap<String, ModuleId>,
     pub module_comb_deps: ModuleVec<ModuleCombDeps>,
     pub gadgets: ModuleVec<Option<PipelineGadget>>,
+    pub loopy_gadgets: ModuleVec<Option<LoopyGadget>>,
 }
 
 impl ModList for ModListBuilder {
@@ -42,11 +45,12 @@ impl ModListBuilder {
             modules: ModuleVec::with_capacity(names.len()),
             module_comb_deps: ModuleVec::with_capacity(names.len()),
             gadgets: ModuleVec::with_capacity(names.len()),
+            loopy_gadgets: ModuleVec::with_capacity(names.len()),
             names,
         }
     }
-    pub fn new(netlist: &yosys::Netlist) -> Result<Self> {
-        let netlist_modules = ModuleVec::from_vec(sort_modules(netlist)?);
+    pub fn new(netlist: &yosys::Netlist, cell_lib: &CellLib) -> Result<Self> {
+        let netlist_modules = ModuleVec::from_vec(sort_modules(netlist, cell_lib)?);
         let mut res = Self::empty_from_names(
             netlist_modules
                 .iter_enumerated()
@@ -54,13 +58,17 @@ impl ModListBuilder {
                 .collect(),
         );
         for (module_id, name) in netlist_modules.iter_enumerated() {
-            let module = Module::from_yosys(&netlist.modules[*name], module_id, name, &res)
-                .with_context(|| format!("Could not build netlist for module {}", name))?;
+            let module =
+                Module::from_yosys(&netlist.modules[*name], module_id, name, &res, cell_lib)
+                    .with_context(|| format!("Could not build netlist for module {}", name))?;
             res.modules.push(module);
             let module = res.module(module_id);
             let gadget = PipelineGadget::new(module, netlist)
                 .with_context(|| format!("Could not build gadget for module {}", module.name))?;
+            let loopy_gadget = LoopyGadget::new(module, netlist)
+                .with_context(|| format!("Could not build gadget for module {}", module.name))?;
             res.gadgets.push(gadget);
+            res.loopy_gadgets.push(loopy_gadget);
             let mut comb_deps = ModuleCombDeps::new(module_id, &res)?;
             let gadget = res.gadget(module_id);
             if let Some(gadget) = gadget {
@@ -72,19 +80,19 @@ impl ModListBuilder {
     }
 }
 
-fn sort_modules(yosys_netlist: &yosys::Netlist) -> Result<Vec<&str>> {
+fn sort_modules<'a>(yosys_netlist: &'a yosys::Netlist, cell_lib: &CellLib) -> Result<Vec<&'a str>> {
     let mut graph = petgraph::Graph::new();
     let mut module_names = yosys_netlist.modules.keys().collect::<Vec<_>>();
     module_names.sort_unstable();
     let name2id = module_names
         .iter()
-        // Exclude Gates from the module list.
-        .filter(|name| !Gate::is_gate(name))
+        // Exclude Gates (and blackbox definitions of expanded cells) from the module list.
+        .filter(|name| !is_cell(name, cell_lib))
         .map(|name| (*name, graph.add_node(name)))
         .collect::<HashMap<_, _>>();
     for module_name in name2id.keys() {
         for (cell_name, cell) in yosys_netlist.modules[*module_name].cells.iter() {
-            if !Gate::is_gate(&cell.cell_type) {
+            if !is_cell(&cell.cell_type, cell_lib) {
                 if yosys_netlist.modules.contains_key(&cell.cell_type) {
                     graph.add_edge(name2id[&cell.cell_type], name2id[module_name], ());
                 } else {
@@ -107,14 +115,24 @@ fn sort_modules(yosys_netlist: &yosys::Netlist) -> Result<Vec<&str>> {
         .map(|node_id| graph[node_id].as_str())
         .collect())
 }
+/// The cell type is a library gate or a cell that is expanded into gates (not a module).
+fn is_cell(cell_type: &str, cell_lib: &CellLib) -> bool {
+    Gate::is_gate(cell_type)
+        || rtl_cells::is_expanded_cell(cell_type)
+        || cell_lib.contains(cell_type)
+}
+
 impl Module {
     pub fn from_yosys(
         yosys_module: &yosys::Module,
         id: ModuleId,
         name: &str,
         modlist: &ModListBuilder,
+        cell_lib: &CellLib,
     ) -> Result<Self> {
-        let module_instances = yosys_ext::module_instances(yosys_module, modlist)?;
+        let mut new_wires = NewWires::new(yosys_ext::count_wires(yosys_module));
+        let module_instances =
+            yosys_ext::module_instances(yosys_module, modlist, cell_lib, &mut new_wires)?;
         let clock_wire = module_clock_wire(module_instances.as_slice(), yosys_module, modlist)?;
         let (ports, clock) = yosys_ext::ports(yosys_module, clock_wire);
         let input_ports = InputVec::from_vec(yosys_ext::filter_ports(
@@ -142,12 +160,12 @@ impl Module {
             ))
             .chain(module_instances.into_iter().map(Ok))
             .collect::<Result<_>>()?;
-        let n_wires = yosys_ext::count_wires(yosys_module);
+        let n_wires = new_wires.n_wires();
         let connection_wires = yosys_ext::connection_wires(yosys_module, &ports)?;
         let port_is_input = yosys_ext::ports_is_input(yosys_module, &ports);
         let wires_output_connection =
             yosys_ext::wires_output_connection(yosys_module, n_wires, &output_ports, &ports)?;
-        let wires_source = wires_source(yosys_module, n_wires, &instances, modlist)?;
+        let wires_source = wires_source(yosys_module, &new_wires, &instances, modlist)?;
         let wire_sinks = wires_sinks(n_wires, &instances, modlist);
         let wires = itertools::izip!(wires_source, wires_output_connection, wire_sinks)
             .map(|(source, output, sinks)| WireProperties {
@@ -156,7 +174,8 @@ impl Module {
                 sinks,
             })
             .collect::<WireVec<_>>();
-        let wire_names = yosys_ext::wire_names(yosys_module, &wires);
+        let wire_names = yosys_ext::wire_names(yosys_module, &wires, &new_wires);
+        let glitch_free = yosys_ext::wires_glitch_free(yosys_module, wires.len())?;
         Ok(Module {
             id,
             name: name.to_owned(),
@@ -169,6 +188,7 @@ impl Module {
             input_ports,
             output_ports,
             wire_names,
+            glitch_free,
         })
     }
 }
@@ -232,11 +252,12 @@ fn module_clock_wire(
 }
 fn wires_source(
     yosys_module: &yosys::Module,
-    n_wires: usize,
+    new_wires: &NewWires,
     instances: &InstanceVec<Instance>,
     netlist: &impl ModList,
 ) -> Result<WireVec<(InstanceId, OutputId)>> {
-    let mut sources = WireVec::<Option<(InstanceId, OutputId)>>::from_vec(vec![None; n_wires]);
+    let mut sources =
+        WireVec::<Option<(InstanceId, OutputId)>>::from_vec(vec![None; new_wires.n_wires()]);
     for (instance_id, instance) in instances.iter_enumerated() {
         for (output_id, output_port) in instance
             .architecture
@@ -247,7 +268,7 @@ fn wires_source(
             if let Some(other_instance) = sources[wire] {
                 bail!(
                     "Wire {} is an output of both {} and {}.",
-                    yosys_ext::wire_name(yosys_module, wire),
+                    yosys_ext::wire_name(yosys_module, new_wires, wire),
                     instances[other_instance.0].name,
                     instance.name
                 );
@@ -264,7 +285,7 @@ fn wires_source(
             } else {
                 bail!(
                     "Could not find driver for wire {}.",
-                    yosys_ext::wire_name(yosys_module, wire),
+                    yosys_ext::wire_name(yosys_module, new_wires, wire),
                 )
             }
         })
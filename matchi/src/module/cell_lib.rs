@@ -0,0 +1,453 @@
+//! User-defined cell library (--cell-lib), whose cells are expanded into LUTs and DFFs.
+//!
+//! The library is a TOML file with one table per cell, e.g.,
+//! ```toml
+//! [AOI21]
+//! inputs = ["A1", "A2", "B"]
+//! outputs = { ZN = "!((A1 & A2) | B)" }
+//!
+//! [DFFR]
+//! inputs = ["CK", "D", "RN"]
+//! outputs = { Q = "IQ", QN = "!IQ" }
+//! ff = { clocked_on = "CK", next_state = "D & RN" }
+//! ```
+//! The functions use the Liberty syntax, and IQ is the state of sequential cells.
+use super::gates::{CombUnitary, Gate, Lut};
+use super::rtl_cells::{CellExpander, NewWires};
+use super::{yosys_ext, Instance, WireId};
+use crate::WireValue;
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use yosys_netlist_json as yosys;
+
+/// Name of the state variable of sequential cells.
+const STATE_VAR: &str = "IQ";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CellDesc {
+    inputs: Vec<String>,
+    outputs: BTreeMap<String, String>,
+    ff: Option<FfDesc>,
+    /// Override of the stop_glitches behavior of the cell (only for sequential cells).
+    stop_glitches: Option<bool>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FfDesc {
+    clocked_on: String,
+    next_state: String,
+}
+
+/// Boolean function of the inputs of a cell. The variables are the indices of the inputs, and
+/// the state of the cell is the variable following the last input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Const(WireValue),
+    Var(usize),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Xor(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+struct LibCell {
+    inputs: Vec<String>,
+    outputs: Vec<(String, Expr)>,
+    /// Clock input and next state function.
+    ff: Option<(usize, Expr)>,
+    stop_glitches: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CellLib {
+    cells: BTreeMap<String, LibCell>,
+}
+
+impl CellLib {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read cell library '{}'.", path))?;
+        let descs: BTreeMap<String, CellDesc> = toml::from_str(&content)
+            .with_context(|| format!("Could not parse cell library '{}'.", path))?;
+        let cells = descs
+            .into_iter()
+            .map(|(name, desc)| {
+                let cell = LibCell::new(desc)
+                    .with_context(|| format!("Invalid cell {} in cell library.", name))?;
+                Ok((name, cell))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { cells })
+    }
+    pub fn contains(&self, cell_type: impl AsRef<str>) -> bool {
+        self.cells.contains_key(cell_type.as_ref())
+    }
+    /// Expand a cell of the library: one LUT per output, and a DFF (with a LUT for its next state)
+    /// for sequential cells.
+    /// module_stop_glitches is the 'matchi_stop_glitches' annotation of the parent module.
+    pub fn expand_cell(
+        &self,
+        cell: &yosys::Cell,
+        cell_name: &str,
+        module_stop_glitches: Option<bool>,
+        new_wires: &mut NewWires,
+    ) -> Result<Vec<Instance>> {
+        let lib_cell = &self.cells[&cell.cell_type];
+        let cell_stop_glitches = yosys_ext::stop_glitches_attr(&cell.attributes)?;
+        if cell_stop_glitches.is_some() && lib_cell.ff.is_none() {
+            bail!(
+                "Attribute 'matchi_stop_glitches' is given on cell {}, which is not sequential.",
+                cell_name
+            );
+        }
+        let stop_glitches = cell_stop_glitches
+            .or(lib_cell.stop_glitches)
+            .or(module_stop_glitches);
+        let mut expander = CellExpander::new(cell, cell_name, new_wires, stop_glitches);
+        lib_cell
+            .expand(&mut expander)
+            .with_context(|| format!("Could not expand cell {}.", cell_name))?;
+        Ok(expander.into_instances())
+    }
+}
+
+impl LibCell {
+    fn new(desc: CellDesc) -> Result<Self> {
+        let n_inputs = desc.inputs.len();
+        let parse = |function: &str, allow_state: bool| {
+            let expr = Expr::parse(function, &desc.inputs, allow_state)
+                .with_context(|| format!("Invalid function '{}'.", function))?;
+            let n_vars = expr.vars(n_inputs + 1).len();
+            if n_vars > Lut::MAX_INPUTS {
+                bail!(
+                    "Function '{}' has {} variables, at most {} are supported.",
+                    function,
+                    n_vars,
+                    Lut::MAX_INPUTS
+                );
+            }
+            Ok(expr)
+        };
+        let ff = desc
+            .ff
+            .as_ref()
+            .map(|ff| {
+                let Some(clock) = desc.inputs.iter().position(|i| *i == ff.clocked_on) else {
+                    bail!(
+                        "Clock '{}' is not an input (only posedge flip-flops are supported).",
+                        ff.clocked_on
+                    );
+                };
+                Ok((clock, parse(&ff.next_state, true)?))
+            })
+            .transpose()?;
+        if desc.stop_glitches.is_some() && ff.is_none() {
+            bail!("'stop_glitches' is only allowed for sequential cells.");
+        }
+        let outputs = desc
+            .outputs
+            .iter()
+            .map(|(output, function)| Ok((output.clone(), parse(function, ff.is_some())?)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            inputs: desc.inputs,
+            outputs,
+            ff,
+            stop_glitches: desc.stop_glitches,
+        })
+    }
+    fn expand(&self, expander: &mut CellExpander) -> Result<()> {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| expander.single_port(input))
+            .collect::<Result<Vec<_>>>()?;
+        let state = if let Some((clock, next_state)) = &self.ff {
+            let state = expander.new_wire(STATE_VAR);
+            let vars = inputs.iter().copied().chain([state]).collect::<Vec<_>>();
+            let d = match next_state {
+                Expr::Var(var) => vars[*var],
+                _ => {
+                    let d = expander.new_wire("next_state");
+                    Self::function_gate(expander, "next_state".to_owned(), next_state, &vars, d)?;
+                    d
+                }
+            };
+            let name = format!("{}.{}", expander.cell_name(), STATE_VAR);
+            expander.dff_gate(name, inputs[*clock], d, state, None, None);
+            Some(state)
+        } else {
+            None
+        };
+        let vars = inputs.iter().copied().chain(state).collect::<Vec<_>>();
+        for (output, function) in &self.outputs {
+            // Unconnected outputs are ignored.
+            if expander.has_port(output) {
+                let output_wire = expander.single_port(output)?;
+                Self::function_gate(expander, output.clone(), function, &vars, output_wire)?;
+            }
+        }
+        Ok(())
+    }
+    /// Gate computing function on the output wire, vars are the wires of the variables.
+    fn function_gate(
+        expander: &mut CellExpander,
+        name: String,
+        function: &Expr,
+        vars: &[WireId],
+        output: WireId,
+    ) -> Result<()> {
+        let name = format!("{}.{}", expander.cell_name(), name);
+        let used_vars = function.vars(vars.len());
+        if used_vars.is_empty() {
+            let value = function.eval(&[]);
+            expander.gate(
+                name,
+                Gate::CombUnitary(CombUnitary::Buf),
+                vec![WireId::from(value), output],
+            );
+            return Ok(());
+        }
+        let table = function.truth_table(&used_vars, vars.len());
+        expander.gate(
+            name,
+            Gate::Lut(Lut::new(used_vars.len(), table)?),
+            [output]
+                .into_iter()
+                .chain(used_vars.iter().map(|var| vars[*var]))
+                .collect(),
+        );
+        Ok(())
+    }
+}
+
+impl Expr {
+    /// Parse a boolean function in the Liberty syntax.
+    /// Operators, by decreasing precedence: ' and ! (not), ^ (xor), & or * or space (and),
+    /// | or + (or).
+    fn parse(function: &str, inputs: &[String], allow_state: bool) -> Result<Self> {
+        let mut parser = ExprParser {
+            chars: function.chars().peekable(),
+            inputs,
+            allow_state,
+        };
+        let res = parser.or()?;
+        if let Some(c) = parser.peek() {
+            bail!("Unexpected character '{}'.", c);
+        }
+        Ok(res)
+    }
+    /// Sorted variables used in the expression (with n_vars the total number of variables).
+    fn vars(&self, n_vars: usize) -> Vec<usize> {
+        let mut used = vec![false; n_vars];
+        self.mark_vars(&mut used);
+        (0..n_vars).filter(|var| used[*var]).collect()
+    }
+    fn mark_vars(&self, used: &mut [bool]) {
+        match self {
+            Expr::Const(_) => {}
+            Expr::Var(var) => used[*var] = true,
+            Expr::Not(op) => op.mark_vars(used),
+            Expr::And(op0, op1) | Expr::Or(op0, op1) | Expr::Xor(op0, op1) => {
+                op0.mark_vars(used);
+                op1.mark_vars(used);
+            }
+        }
+    }
+    /// Truth table of the expression, as a function of the used_vars (with n_vars the total number
+    /// of variables): bit i of the table is the value when used_vars[j] is bit j of i.
+    fn truth_table(&self, used_vars: &[usize], n_vars: usize) -> u64 {
+        let mut table = 0;
+        for index in 0..(1 << used_vars.len()) {
+            let mut values = vec![WireValue::_0; n_vars];
+            for (i, var) in used_vars.iter().enumerate() {
+                values[*var] = WireValue::from((index >> i) & 1 == 1);
+            }
+            if self.eval(&values) == WireValue::_1 {
+                table |= 1 << index;
+            }
+        }
+        table
+    }
+    fn eval(&self, values: &[WireValue]) -> WireValue {
+        match self {
+            Expr::Const(value) => *value,
+            Expr::Var(var) => values[*var],
+            Expr::Not(op) => !op.eval(values),
+            Expr::And(op0, op1) => op0.eval(values) & op1.eval(values),
+            Expr::Or(op0, op1) => op0.eval(values) | op1.eval(values),
+            Expr::Xor(op0, op1) => op0.eval(values) ^ op1.eval(values),
+        }
+    }
+}
+
+/// Recursive-descent parser for Liberty boolean functions.
+struct ExprParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    inputs: &'a [String],
+    allow_state: bool,
+}
+
+impl ExprParser<'_> {
+    /// Next non-whitespace character.
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+    fn or(&mut self) -> Result<Expr> {
+        let mut res = self.and()?;
+        while let Some('|' | '+') = self.peek() {
+            self.chars.next();
+            res = Expr::Or(Box::new(res), Box::new(self.and()?));
+        }
+        Ok(res)
+    }
+    fn and(&mut self) -> Result<Expr> {
+        let mut res = self.xor()?;
+        loop {
+            match self.peek() {
+                Some('&' | '*') => {
+                    self.chars.next();
+                }
+                // Implicit and (juxtaposition).
+                Some(c) if c == '(' || c == '!' || is_ident_char(c) => {}
+                _ => return Ok(res),
+            }
+            res = Expr::And(Box::new(res), Box::new(self.xor()?));
+        }
+    }
+    fn xor(&mut self) -> Result<Expr> {
+        let mut res = self.unary()?;
+        while let Some('^') = self.peek() {
+            self.chars.next();
+            res = Expr::Xor(Box::new(res), Box::new(self.unary()?));
+        }
+        Ok(res)
+    }
+    fn unary(&mut self) -> Result<Expr> {
+        let mut res = match self.peek() {
+            Some('!') => {
+                self.chars.next();
+                Expr::Not(Box::new(self.unary()?))
+            }
+            Some('(') => {
+                self.chars.next();
+                let res = self.or()?;
+                if self.peek() != Some(')') {
+                    bail!("Missing closing parenthesis.");
+                }
+                self.chars.next();
+                res
+            }
+            Some(c) if is_ident_char(c) => self.atom()?,
+            Some(c) => bail!("Unexpected character '{}'.", c),
+            None => bail!("Unexpected end of function."),
+        };
+        while let Some('\'') = self.peek() {
+            self.chars.next();
+            res = Expr::Not(Box::new(res));
+        }
+        Ok(res)
+    }
+    fn atom(&mut self) -> Result<Expr> {
+        let mut name = String::new();
+        while let Some(c) = self.chars.next_if(|c| is_ident_char(*c)) {
+            name.push(c);
+        }
+        Ok(match name.as_str() {
+            "0" => Expr::Const(WireValue::_0),
+            "1" => Expr::Const(WireValue::_1),
+            STATE_VAR if self.allow_state => Expr::Var(self.inputs.len()),
+            _ => Expr::Var(
+                self.inputs
+                    .iter()
+                    .position(|input| *input == name)
+                    .ok_or_else(|| anyhow!("'{}' is not an input of the cell.", name))?,
+            ),
+        })
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '[' || c == ']'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(function: &str, inputs: &[&str]) -> Expr {
+        let inputs = inputs.iter().map(|s| (*s).to_owned()).collect::<Vec<_>>();
+        Expr::parse(function, &inputs, false).unwrap()
+    }
+    fn table(function: &str, inputs: &[&str]) -> u64 {
+        let expr = parse(function, inputs);
+        expr.truth_table(&expr.vars(inputs.len()), inputs.len())
+    }
+    fn var(i: usize) -> Box<Expr> {
+        Box::new(Expr::Var(i))
+    }
+
+    #[test]
+    fn precedence() {
+        let inputs = ["A", "B", "C"];
+        assert_eq!(
+            parse("A | B & C", &inputs),
+            Expr::Or(var(0), Box::new(Expr::And(var(1), var(2))))
+        );
+        assert_eq!(
+            parse("A + B * C", &inputs),
+            Expr::Or(var(0), Box::new(Expr::And(var(1), var(2))))
+        );
+        assert_eq!(
+            parse("A & B ^ C", &inputs),
+            Expr::And(var(0), Box::new(Expr::Xor(var(1), var(2))))
+        );
+        assert_eq!(
+            parse("A B | C", &inputs),
+            Expr::Or(Box::new(Expr::And(var(0), var(1))), var(2))
+        );
+        assert_eq!(
+            parse("!A ^ B", &inputs),
+            Expr::Xor(Box::new(Expr::Not(var(0))), var(1))
+        );
+    }
+
+    #[test]
+    fn negation_and_parentheses() {
+        let inputs = ["A", "B"];
+        assert_eq!(parse("A'", &inputs), Expr::Not(var(0)));
+        assert_eq!(parse("!A", &inputs), parse("A'", &inputs));
+        assert_eq!(
+            parse("(A | B)'", &inputs),
+            Expr::Not(Box::new(Expr::Or(var(0), var(1))))
+        );
+        assert_eq!(parse("!(A | B)", &inputs), parse("(A | B)'", &inputs));
+        assert_eq!(parse("A (B)", &inputs), Expr::And(var(0), var(1)));
+        assert_eq!(
+            parse("A'B", &inputs),
+            Expr::And(Box::new(Expr::Not(var(0))), var(1))
+        );
+        let inputs = ["A".to_owned()];
+        assert!(Expr::parse("(A", &inputs, false).is_err());
+        assert!(Expr::parse("A)", &inputs, false).is_err());
+        assert!(Expr::parse("B", &inputs, false).is_err());
+        assert!(Expr::parse("IQ", &inputs, false).is_err());
+        assert_eq!(Expr::parse("IQ", &inputs, true).unwrap(), Expr::Var(1));
+    }
+
+    #[test]
+    fn truth_tables() {
+        // Input 0 is the least significant bit of the table index.
+        assert_eq!(table("A", &["A", "B"]), 0b10);
+        assert_eq!(table("B", &["A", "B"]), 0b10);
+        assert_eq!(table("A & !B", &["A", "B"]), 0b0010);
+        assert_eq!(table("!((A1 & A2) | B)", &["A1", "A2", "B"]), 0b0000_0111);
+        assert_eq!(table("(A & !S) | (B & S)", &["A", "B", "S"]), 0b1100_1010);
+        assert_eq!(table("A ^ B ^ C", &["A", "B", "C"]), 0b1001_0110);
+        assert_eq!(table("0", &["A"]), 0);
+    }
+}
@@ -1,4 +1,4 @@
-use super::{ConnectionId, InputSlice, OutputSlice, WireName};
+use super::{ConnectionId, InputId, InputSlice, OutputId, OutputSlice, WireName};
 use crate::WireValue;
 use anyhow::{bail, Result};
 
@@ -11,19 +11,63 @@ pub enum CombUnitary {
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CombBinary {
     And,
-    //    Nand,
+    Nand,
     Or,
-    //    Nor,
+    Nor,
     Xor,
-    //    Xnor,
+    Xnor,
+    /// A & ~B
+    AndNot,
+    /// A | ~B
+    OrNot,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Gate {
     CombUnitary(CombUnitary),
     CombBinary(CombBinary),
-    Mux,
-    Dff,
+    /// Output is inverted if 'inverted'.
+    Mux {
+        inverted: bool,
+    },
+    Dff(Dff),
+    Latch(Latch),
+    Lut(Lut),
+    Adder(Adder),
+}
+
+/// Posedge flip-flop, with optional enable and synchronous reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Dff {
+    /// Active level of the enable input 'E'.
+    pub enable: Option<WireValue>,
+    /// Active level of the synchronous reset input 'R', and reset value. The reset has priority
+    /// over the enable.
+    pub reset: Option<(WireValue, WireValue)>,
+}
+
+/// Level-sensitive latch, transparent when the enable input 'E' is at its active level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Latch {
+    pub enable: WireValue,
+}
+
+/// Adder with sum (S) and carry (CO) outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Adder {
+    /// Inputs A and B.
+    Half,
+    /// Inputs A, B and CI.
+    Full,
+}
+
+/// Look-up table with an arbitrary truth table (e.g., FPGA LUT).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lut {
+    n_inputs: usize,
+    /// Bit i is the output when the inputs are the binary representation of i (with input 0 as
+    /// least significant bit).
+    table: u64,
 }
 
 impl std::str::FromStr for Gate {
@@ -35,22 +79,204 @@ impl std::str::FromStr for Gate {
             "BUF" => Self::CombUnitary(Buf),
             "NOT" => Self::CombUnitary(Not),
             "AND" => Self::CombBinary(And),
-            //            "NAND" => Self::CombBinary(Nand),
+            "NAND" => Self::CombBinary(Nand),
             "OR" => Self::CombBinary(Or),
-            //            "NOR" => Self::CombBinary(Nor),
+            "NOR" => Self::CombBinary(Nor),
             "XOR" => Self::CombBinary(Xor),
-            //            "XNOR" => Self::CombBinary(Xnor),
-            "MUX" => Self::Mux,
-            "DFF" => Self::Dff,
-            _ => bail!("'{}' is not a matchi_cells gate.", s),
+            "XNOR" => Self::CombBinary(Xnor),
+            "MUX" => Self::Mux { inverted: false },
+            "LATCH" => Self::Latch(Latch {
+                enable: WireValue::_1,
+            }),
+            "HA" => Self::Adder(Adder::Half),
+            "FA" => Self::Adder(Adder::Full),
+            "DFF" => Self::Dff(Dff::default()),
+            "DFFE" => Self::Dff(Dff {
+                enable: Some(WireValue::_1),
+                reset: None,
+            }),
+            "SDFF" => Self::Dff(Dff {
+                enable: None,
+                reset: Some((WireValue::_1, WireValue::_0)),
+            }),
+            "SDFFE" => Self::Dff(Dff {
+                enable: Some(WireValue::_1),
+                reset: Some((WireValue::_1, WireValue::_0)),
+            }),
+            // Yosys internal gate library.
+            "$_BUF_" => Self::CombUnitary(Buf),
+            "$_NOT_" => Self::CombUnitary(Not),
+            "$_AND_" => Self::CombBinary(And),
+            "$_NAND_" => Self::CombBinary(Nand),
+            "$_OR_" => Self::CombBinary(Or),
+            "$_NOR_" => Self::CombBinary(Nor),
+            "$_XOR_" => Self::CombBinary(Xor),
+            "$_XNOR_" => Self::CombBinary(Xnor),
+            "$_ANDNOT_" => Self::CombBinary(AndNot),
+            "$_ORNOT_" => Self::CombBinary(OrNot),
+            "$_MUX_" => Self::Mux { inverted: false },
+            "$_NMUX_" => Self::Mux { inverted: true },
+            "$_DLATCH_P_" => Self::Latch(Latch {
+                enable: WireValue::_1,
+            }),
+            "$_DLATCH_N_" => Self::Latch(Latch {
+                enable: WireValue::_0,
+            }),
+            _ => {
+                if let Some(dff) = Dff::from_yosys_name(s) {
+                    Self::Dff(dff?)
+                } else {
+                    bail!(
+                        "'{}' is not a matchi_cells gate, nor a supported Yosys internal gate.",
+                        s
+                    )
+                }
+            }
+        })
+    }
+}
+
+impl Dff {
+    /// Parse the name of a Yosys internal flip-flop cell ($_DFF_P_, $_DFFE_PP_, $_SDFF_PP0_,
+    /// $_SDFFE_PP0P_ and their variants), returns None if the name does not match.
+    fn from_yosys_name(s: &str) -> Option<Result<Self>> {
+        let (kind, flags) = s.strip_prefix("$_")?.strip_suffix('_')?.split_once('_')?;
+        let (has_reset, has_enable) = match kind {
+            "DFF" => (false, false),
+            "DFFE" => (false, true),
+            "SDFF" => (true, false),
+            "SDFFE" => (true, true),
+            _ => return None,
+        };
+        let mut flags = flags.chars();
+        let mut next_value = |one: char, zero: char| match flags.next()? {
+            c if c == one => Some(WireValue::_1),
+            c if c == zero => Some(WireValue::_0),
+            _ => None,
+        };
+        let clock = next_value('P', 'N')?;
+        let reset = if has_reset {
+            Some((next_value('P', 'N')?, next_value('1', '0')?))
+        } else {
+            None
+        };
+        let enable = if has_enable {
+            Some(next_value('P', 'N')?)
+        } else {
+            None
+        };
+        if flags.next().is_some() {
+            return None;
+        }
+        Some(if clock == WireValue::_1 {
+            Ok(Dff { enable, reset })
+        } else {
+            Err(anyhow::anyhow!(
+                "Negative-edge DFFs ('{}') are not supported.",
+                s
+            ))
         })
     }
+    /// Input for the enable, if any.
+    pub fn enable_input(&self) -> Option<InputId> {
+        self.enable.map(|_| InputId::from_usize(2))
+    }
+    /// Input for the reset, if any.
+    pub fn reset_input(&self) -> Option<InputId> {
+        self.reset
+            .map(|_| InputId::from_usize(2 + usize::from(self.enable.is_some())))
+    }
+}
+
+impl Lut {
+    pub const MAX_INPUTS: usize = 6;
+    pub fn new(n_inputs: usize, table: u64) -> Result<Self> {
+        if n_inputs == 0 || n_inputs > Self::MAX_INPUTS {
+            bail!(
+                "LUTs must have between 1 and {} inputs (got {}).",
+                Self::MAX_INPUTS,
+                n_inputs
+            );
+        }
+        Ok(Self {
+            n_inputs,
+            table: table & Self::mask(n_inputs),
+        })
+    }
+    fn mask(n_inputs: usize) -> u64 {
+        u64::MAX >> (64 - (1 << n_inputs))
+    }
+    pub fn n_inputs(&self) -> usize {
+        self.n_inputs
+    }
+    fn entry(&self, index: usize) -> WireValue {
+        WireValue::from((self.table >> index) & 1 == 1)
+    }
+    /// LUT (over the same inputs) where the inputs with a Some value are fixed to that value.
+    pub fn restrict(&self, values: &[Option<WireValue>]) -> Self {
+        let mut table = 0;
+        for index in 0..(1 << self.n_inputs) {
+            let src = values
+                .iter()
+                .enumerate()
+                .fold(index, |src, (i, value)| match value {
+                    Some(WireValue::_0) => src & !(1 << i),
+                    Some(WireValue::_1) => src | (1 << i),
+                    None => src,
+                });
+            table |= ((self.table >> src) & 1) << index;
+        }
+        Self {
+            n_inputs: self.n_inputs,
+            table,
+        }
+    }
+    /// The output depends on the value of the input.
+    pub fn depends_on(&self, input: usize) -> bool {
+        (0..(1 << self.n_inputs))
+            .filter(|index| index & (1 << input) == 0)
+            .any(|index| self.entry(index) != self.entry(index | (1 << input)))
+    }
+    /// Output value, if it is the same for all the values of the unknown (None) inputs.
+    pub fn opx(&self, values: &[Option<WireValue>]) -> Option<WireValue> {
+        let restricted = self.restrict(values);
+        if restricted.table == 0 {
+            Some(WireValue::_0)
+        } else if restricted.table == Self::mask(self.n_inputs) {
+            Some(WireValue::_1)
+        } else {
+            None
+        }
+    }
+}
+
+impl Latch {
+    pub const ENABLE_INPUT: InputId = InputId::from_raw_unchecked(0);
+    pub const D_INPUT: InputId = InputId::from_raw_unchecked(1);
+}
+
+impl Adder {
+    /// Function of an output (S or CO).
+    pub fn output_function(&self, output: OutputId) -> Lut {
+        let (n_inputs, sum, carry) = match self {
+            Adder::Half => (2, 0b0110, 0b1000),
+            Adder::Full => (3, 0b1001_0110, 0b1110_1000),
+        };
+        Lut {
+            n_inputs,
+            table: if output.index() == 0 { sum } else { carry },
+        }
+    }
 }
 
 impl Gate {
     pub fn is_gate(s: impl AsRef<str>) -> bool {
         s.as_ref().parse::<Gate>().is_ok()
     }
+    /// DFFs and latches.
+    pub fn is_sequential(&self) -> bool {
+        matches!(self, Gate::Dff(_) | Gate::Latch(_))
+    }
     pub fn connections(&self) -> &'static [WireName<&'static str>] {
         const WA: WireName<&'static str> = WireName::single_port("A");
         const WB: WireName<&'static str> = WireName::single_port("B");
@@ -59,11 +285,35 @@ impl Gate {
         const WC: WireName<&'static str> = WireName::single_port("C");
         const WD: WireName<&'static str> = WireName::single_port("D");
         const WQ: WireName<&'static str> = WireName::single_port("Q");
+        const WE: WireName<&'static str> = WireName::single_port("E");
+        const WR: WireName<&'static str> = WireName::single_port("R");
+        const WCI: WireName<&'static str> = WireName::single_port("CI");
+        const WSUM: WireName<&'static str> = WireName::single_port("S");
+        const WCO: WireName<&'static str> = WireName::single_port("CO");
+        // Output first, such that the connections of LUTs with fewer inputs are a prefix.
+        const LUT_CONNECTIONS: [WireName<&'static str>; 1 + Lut::MAX_INPUTS] = [
+            WireName::single_port("O"),
+            WireName::single_port("I0"),
+            WireName::single_port("I1"),
+            WireName::single_port("I2"),
+            WireName::single_port("I3"),
+            WireName::single_port("I4"),
+            WireName::single_port("I5"),
+        ];
         match self {
             Gate::CombUnitary(_) => [WA, WY].as_slice(),
             Gate::CombBinary(_) => [WA, WB, WY].as_slice(),
-            Gate::Mux => [WA, WB, WS, WY].as_slice(),
-            Gate::Dff => [WC, WD, WQ].as_slice(),
+            Gate::Mux { .. } => [WA, WB, WS, WY].as_slice(),
+            Gate::Dff(dff) => match (dff.enable.is_some(), dff.reset.is_some()) {
+                (false, false) => [WC, WD, WQ].as_slice(),
+                (true, false) => [WC, WD, WE, WQ].as_slice(),
+                (false, true) => [WC, WD, WR, WQ].as_slice(),
+                (true, true) => [WC, WD, WE, WR, WQ].as_slice(),
+            },
+            Gate::Latch(_) => [WE, WD, WQ].as_slice(),
+            Gate::Lut(lut) => &LUT_CONNECTIONS[..=lut.n_inputs],
+            Gate::Adder(Adder::Half) => [WA, WB, WSUM, WCO].as_slice(),
+            Gate::Adder(Adder::Full) => [WA, WB, WCI, WSUM, WCO].as_slice(),
         }
     }
     pub fn input_ports(&self) -> &'static InputSlice<ConnectionId> {
@@ -77,30 +327,68 @@ impl Gate {
             ConnectionId::from_raw_unchecked(1),
             ConnectionId::from_raw_unchecked(2),
         ];
-        const DFF_INPUTS: [ConnectionId; 2] = [
+        const DFF_INPUTS: [ConnectionId; 4] = [
             ConnectionId::from_raw_unchecked(0),
             ConnectionId::from_raw_unchecked(1),
+            ConnectionId::from_raw_unchecked(2),
+            ConnectionId::from_raw_unchecked(3),
+        ];
+        const LUT_INPUTS: [ConnectionId; Lut::MAX_INPUTS] = [
+            ConnectionId::from_raw_unchecked(1),
+            ConnectionId::from_raw_unchecked(2),
+            ConnectionId::from_raw_unchecked(3),
+            ConnectionId::from_raw_unchecked(4),
+            ConnectionId::from_raw_unchecked(5),
+            ConnectionId::from_raw_unchecked(6),
         ];
         InputSlice::from_slice(match self {
             Gate::CombUnitary(_) => UNITARY_INPUTS.as_slice(),
             Gate::CombBinary(_) => BINARY_INPUTS.as_slice(),
-            Gate::Mux => MUX_INPUTS.as_slice(),
-            Gate::Dff => DFF_INPUTS.as_slice(),
+            Gate::Mux { .. } => MUX_INPUTS.as_slice(),
+            // All the connections except the output Q.
+            Gate::Dff(_) => &DFF_INPUTS[..self.connections().len() - 1],
+            Gate::Latch(_) => BINARY_INPUTS.as_slice(),
+            Gate::Lut(lut) => &LUT_INPUTS[..lut.n_inputs],
+            Gate::Adder(Adder::Half) => BINARY_INPUTS.as_slice(),
+            Gate::Adder(Adder::Full) => MUX_INPUTS.as_slice(),
         })
     }
     pub fn output_ports(&self) -> &'static OutputSlice<ConnectionId> {
         const UNITARY_OUTPUTS: [ConnectionId; 1] = [ConnectionId::from_raw_unchecked(1)];
         const BINARY_OUTPUTS: [ConnectionId; 1] = [ConnectionId::from_raw_unchecked(2)];
         const MUX_OUTPUTS: [ConnectionId; 1] = [ConnectionId::from_raw_unchecked(3)];
-        const DFF_OUTPUTS: [ConnectionId; 1] = [ConnectionId::from_raw_unchecked(2)];
+        const DFF_OUTPUTS: [ConnectionId; 3] = [
+            ConnectionId::from_raw_unchecked(2),
+            ConnectionId::from_raw_unchecked(3),
+            ConnectionId::from_raw_unchecked(4),
+        ];
+        const LUT_OUTPUTS: [ConnectionId; 1] = [ConnectionId::from_raw_unchecked(0)];
+        const HALF_ADDER_OUTPUTS: [ConnectionId; 2] = [
+            ConnectionId::from_raw_unchecked(2),
+            ConnectionId::from_raw_unchecked(3),
+        ];
+        const FULL_ADDER_OUTPUTS: [ConnectionId; 2] = [
+            ConnectionId::from_raw_unchecked(3),
+            ConnectionId::from_raw_unchecked(4),
+        ];
         OutputSlice::from_slice(match self {
             Gate::CombUnitary(_) => UNITARY_OUTPUTS.as_slice(),
             Gate::CombBinary(_) => BINARY_OUTPUTS.as_slice(),
-            Gate::Mux => MUX_OUTPUTS.as_slice(),
-            Gate::Dff => DFF_OUTPUTS.as_slice(),
+            Gate::Mux { .. } => MUX_OUTPUTS.as_slice(),
+            // Q is the last connection.
+            Gate::Dff(_) => {
+                let n_inputs = self.connections().len() - 1;
+                &DFF_OUTPUTS[n_inputs - 2..n_inputs - 1]
+            }
+            Gate::Latch(_) => BINARY_OUTPUTS.as_slice(),
+            Gate::Lut(_) => LUT_OUTPUTS.as_slice(),
+            Gate::Adder(Adder::Half) => HALF_ADDER_OUTPUTS.as_slice(),
+            Gate::Adder(Adder::Full) => FULL_ADDER_OUTPUTS.as_slice(),
         })
     }
-    pub fn comb_deps(&self) -> &'static [ConnectionId] {
+    /// Inputs on which the output has a combinational dependency.
+    pub fn comb_deps(&self, output: ConnectionId) -> &'static [ConnectionId] {
+        assert!(self.output_ports().iter().any(|con| *con == output));
         const UNITARY_DEPS: [ConnectionId; 1] = [ConnectionId::from_raw_unchecked(0)];
         const BINARY_DEPS: [ConnectionId; 2] = [
             ConnectionId::from_raw_unchecked(0),
@@ -115,33 +403,92 @@ impl Gate {
         match self {
             Gate::CombUnitary(_) => UNITARY_DEPS.as_slice(),
             Gate::CombBinary(_) => BINARY_DEPS.as_slice(),
-            Gate::Mux => MUX_DEPS.as_slice(),
-            Gate::Dff => DFF_DEPS.as_slice(),
+            Gate::Mux { .. } => MUX_DEPS.as_slice(),
+            Gate::Dff(_) => DFF_DEPS.as_slice(),
+            // Transparent latch.
+            Gate::Latch(_) => BINARY_DEPS.as_slice(),
+            // All the outputs depend on all the inputs.
+            Gate::Lut(_) | Gate::Adder(_) => self.input_ports().as_raw_slice(),
+        }
+    }
+    /// Function of the output, for the gates that are simulated as LUTs.
+    pub fn lut_function(&self, output: OutputId) -> Option<Lut> {
+        match self {
+            Gate::Lut(lut) => Some(*lut),
+            Gate::Adder(adder) => Some(adder.output_function(output)),
+            _ => None,
         }
     }
     pub fn clock(&self) -> Option<WireName<&'static str>> {
         match self {
-            Gate::CombUnitary(_) | Gate::CombBinary(_) | Gate::Mux => None,
-            Gate::Dff => Some(WireName::single_port("C")),
+            Gate::CombUnitary(_)
+            | Gate::CombBinary(_)
+            | Gate::Mux { .. }
+            | Gate::Latch(_)
+            | Gate::Lut(_)
+            | Gate::Adder(_) => None,
+            Gate::Dff(_) => Some(WireName::single_port("C")),
         }
     }
 }
 
 impl CombBinary {
-    pub fn neutral(&self) -> WireValue {
+    /// Non-inverted gate (e.g., And for Nand and AndNot).
+    pub fn base(&self) -> CombBinary {
         match self {
+            CombBinary::And | CombBinary::Nand | CombBinary::AndNot => CombBinary::And,
+            CombBinary::Or | CombBinary::Nor | CombBinary::OrNot => CombBinary::Or,
+            CombBinary::Xor | CombBinary::Xnor => CombBinary::Xor,
+        }
+    }
+    /// The output is the negation of the output of the base gate.
+    pub fn inverted(&self) -> bool {
+        matches!(self, CombBinary::Nand | CombBinary::Nor | CombBinary::Xnor)
+    }
+    /// The second input is negated before the base gate.
+    pub fn inverted_b(&self) -> bool {
+        matches!(self, CombBinary::AndNot | CombBinary::OrNot)
+    }
+    /// Input value for which the output is the other input (or its negation, for inverted gates).
+    pub fn neutral(&self) -> WireValue {
+        match self.base() {
             CombBinary::And => WireValue::_1,
-            CombBinary::Or | CombBinary::Xor => WireValue::_0,
+            _ => WireValue::_0,
         }
     }
+    /// Input value which determines the output regardless of the other input.
     pub fn absorb(&self) -> Option<WireValue> {
-        match self {
+        match self.base() {
             CombBinary::And => Some(WireValue::_0),
             CombBinary::Or => Some(WireValue::_1),
-            CombBinary::Xor => None,
+            _ => None,
         }
     }
+    /// Value of an input (0 for A, 1 for B) which determines the output, taking into account the
+    /// inversion of the second input.
+    pub fn input_absorb(&self, input: usize) -> Option<WireValue> {
+        self.absorb().map(|v| {
+            if input == 1 && self.inverted_b() {
+                !v
+            } else {
+                v
+            }
+        })
+    }
     pub fn opx(&self, op0: Option<WireValue>, op1: Option<WireValue>) -> Option<WireValue> {
+        let op1 = if self.inverted_b() {
+            op1.map(|v| !v)
+        } else {
+            op1
+        };
+        let res = self.base().base_opx(op0, op1);
+        if self.inverted() {
+            res.map(|v| !v)
+        } else {
+            res
+        }
+    }
+    fn base_opx(&self, op0: Option<WireValue>, op1: Option<WireValue>) -> Option<WireValue> {
         match (self, op0, op1) {
             (CombBinary::And, Some(op0), Some(op1)) => Some(op0 & op1),
             (CombBinary::And, Some(WireValue::_0), None)
@@ -157,6 +504,134 @@ impl CombBinary {
             | (CombBinary::Or, None, None) => None,
             (CombBinary::Xor, Some(op0), Some(op1)) => Some(op0 ^ op1),
             (CombBinary::Xor, None, _) | (CombBinary::Xor, _, None) => None,
+            (
+                CombBinary::Nand
+                | CombBinary::Nor
+                | CombBinary::Xnor
+                | CombBinary::AndNot
+                | CombBinary::OrNot,
+                _,
+                _,
+            ) => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of a LUT for the given inputs (input 0 is the least significant bit).
+    fn eval(lut: &Lut, inputs: usize) -> WireValue {
+        let values = (0..lut.n_inputs())
+            .map(|i| Some(WireValue::from((inputs >> i) & 1 == 1)))
+            .collect::<Vec<_>>();
+        lut.opx(&values).unwrap()
+    }
+    fn cons(ids: &[u32]) -> Vec<ConnectionId> {
+        ids.iter().copied().map(ConnectionId::from_raw).collect()
+    }
+
+    #[test]
+    fn adder_tables() {
+        for (adder, n_inputs) in [(Adder::Half, 2), (Adder::Full, 3)] {
+            let sum = adder.output_function(OutputId::from_raw(0));
+            let carry = adder.output_function(OutputId::from_raw(1));
+            for inputs in 0..(1 << n_inputs) {
+                let n_ones = (inputs as u32).count_ones();
+                assert_eq!(eval(&sum, inputs), WireValue::from(n_ones % 2 == 1));
+                assert_eq!(eval(&carry, inputs), WireValue::from(n_ones >= 2));
+            }
+        }
+    }
+
+    #[test]
+    fn lut_function() {
+        let lut = Lut::new(3, 0b1000_0000).unwrap();
+        assert_eq!(
+            Gate::Lut(lut).lut_function(OutputId::from_raw(0)),
+            Some(lut)
+        );
+        assert_eq!(
+            Gate::Adder(Adder::Full).lut_function(OutputId::from_raw(1)),
+            Some(Adder::Full.output_function(OutputId::from_raw(1)))
+        );
+        assert_eq!(
+            Gate::CombBinary(CombBinary::And).lut_function(OutputId::from_raw(0)),
+            None
+        );
+        assert_eq!(eval(&lut, 0b111), WireValue::_1);
+        assert_eq!(eval(&lut, 0b011), WireValue::_0);
+        // Bits of the table above the inputs are ignored.
+        assert_eq!(Lut::new(2, 0xf8).unwrap(), Lut::new(2, 0x8).unwrap());
+        assert!(Lut::new(0, 0).is_err());
+        assert!(Lut::new(Lut::MAX_INPUTS + 1, 0).is_err());
+    }
+
+    #[test]
+    fn dff_from_yosys_name() {
+        let dff = |s: &str| Dff::from_yosys_name(s).map(|dff| dff.ok());
+        assert_eq!(dff("$_DFF_P_"), Some(Some(Dff::default())));
+        assert_eq!(
+            dff("$_DFFE_PN_"),
+            Some(Some(Dff {
+                enable: Some(WireValue::_0),
+                reset: None,
+            }))
+        );
+        assert_eq!(
+            dff("$_SDFF_PN1_"),
+            Some(Some(Dff {
+                enable: None,
+                reset: Some((WireValue::_0, WireValue::_1)),
+            }))
+        );
+        assert_eq!(
+            dff("$_SDFFE_PP0N_"),
+            Some(Some(Dff {
+                enable: Some(WireValue::_0),
+                reset: Some((WireValue::_1, WireValue::_0)),
+            }))
+        );
+        // Negative edge.
+        assert_eq!(dff("$_DFF_N_"), Some(None));
+        // Asynchronous reset, and malformed names.
+        assert_eq!(dff("$_DFF_PP0_"), None);
+        assert_eq!(dff("$_DFFSR_PPP_"), None);
+        assert_eq!(dff("$_SDFF_PP2_"), None);
+        assert_eq!(dff("$_DFF_P"), None);
+        assert!("$_DFF_N_".parse::<Gate>().is_err());
+        assert!("$_DFF_PP0_".parse::<Gate>().is_err());
+        assert_eq!(
+            "$_DFFE_PP_".parse::<Gate>().unwrap(),
+            "DFFE".parse::<Gate>().unwrap()
+        );
+    }
+
+    #[test]
+    fn comb_deps() {
+        let sdffe = Gate::Dff(Dff {
+            enable: Some(WireValue::_1),
+            reset: Some((WireValue::_1, WireValue::_0)),
+        });
+        assert_eq!(sdffe.output_ports().as_raw_slice(), cons(&[4]));
+        assert_eq!(sdffe.comb_deps(ConnectionId::from_raw(4)), cons(&[]));
+        let latch = Gate::Latch(Latch {
+            enable: WireValue::_1,
+        });
+        assert_eq!(latch.comb_deps(ConnectionId::from_raw(2)), cons(&[0, 1]));
+        let mux = Gate::Mux { inverted: true };
+        assert_eq!(mux.comb_deps(ConnectionId::from_raw(3)), cons(&[0, 1, 2]));
+        // The output of a LUT is its first connection.
+        let lut = Gate::Lut(Lut::new(2, 0b0110).unwrap());
+        assert_eq!(lut.output_ports().as_raw_slice(), cons(&[0]));
+        assert_eq!(lut.comb_deps(ConnectionId::from_raw(0)), cons(&[1, 2]));
+        let full_adder = Gate::Adder(Adder::Full);
+        for output in [3, 4] {
+            assert_eq!(
+                full_adder.comb_deps(ConnectionId::from_raw(output)),
+                cons(&[0, 1, 2])
+            );
         }
     }
 }
@@ -15,6 +15,9 @@ pub struct Instance {
     pub name: String,
     pub architecture: InstanceType,
     pub connections: ConnectionVec<WireId>,
+    /// For DFFs and latches, 'matchi_stop_glitches' annotation of the cell (or of its parent
+    /// module).
+    pub stop_glitches: Option<bool>,
 }
 
 #[derive(Debug, Clone)]
@@ -71,10 +74,7 @@ impl InstanceType {
         netlist: &impl ModList,
     ) -> Result<std::borrow::Cow<[ConnectionId]>> {
         Ok(match self {
-            InstanceType::Gate(gate) => {
-                assert_eq!(gate.output_ports(), &[output]);
-                gate.comb_deps().into()
-            }
+            InstanceType::Gate(gate) => gate.comb_deps(output).into(),
             InstanceType::Module(module_id) => {
                 let module = netlist.module(*module_id);
                 netlist
@@ -96,14 +96,27 @@ impl Instance {
             name: "clock".to_owned(),
             architecture: InstanceType::Clock,
             connections: ConnectionVec::from_vec(vec![wire]),
+            stop_glitches: None,
         }
     }
     pub(super) fn from_cell(
         cell: &yosys::Cell,
         name: &str,
         netlist: &impl ModList,
+        module_stop_glitches: Option<bool>,
     ) -> Result<Self> {
         let architecture = InstanceType::from_cell(cell, netlist)?;
+        let cell_stop_glitches = yosys_ext::stop_glitches_attr(&cell.attributes)?;
+        let sequential = matches!(&architecture, InstanceType::Gate(gate) if gate.is_sequential());
+        if cell_stop_glitches.is_some() && !sequential {
+            bail!(
+                "Attribute 'matchi_stop_glitches' is given on cell {}, which is not sequential.",
+                name
+            );
+        }
+        let stop_glitches = sequential
+            .then(|| cell_stop_glitches.or(module_stop_glitches))
+            .flatten();
         let connections = match architecture {
             InstanceType::Gate(gate) => yosys_ext::cell_connection_wires(cell, gate.connections())?,
             InstanceType::Module(module_id) => {
@@ -115,6 +128,7 @@ impl Instance {
             name: name.to_owned(),
             architecture,
             connections,
+            stop_glitches,
         })
     }
     pub(super) fn from_input_of(
@@ -131,6 +145,7 @@ impl Instance {
             name: format!("input:{}", ports[connection_id]),
             architecture,
             connections,
+            stop_glitches: None,
         })
     }
 
@@ -145,7 +160,18 @@ impl Instance {
                 .clock
                 .as_ref()
                 .map(WireName::as_name_ref),
-            InstanceType::Gate(gate) => gate.clock(),
+            InstanceType::Gate(gate) => {
+                // Gates may come from bit-blasted cells, hence the clock is taken from the
+                // connections of the instance (not from the yosys cell).
+                return gate.clock().map(|clock| {
+                    let con_id = gate
+                        .connections()
+                        .iter()
+                        .position(|con| con.name == clock.name && con.offset == clock.offset)
+                        .unwrap();
+                    self.connections[ConnectionId::from_usize(con_id)]
+                });
+            }
             InstanceType::Input(..) | InstanceType::Tie(_) | InstanceType::Clock => None,
         };
         clock_name.map(|clock_name| {
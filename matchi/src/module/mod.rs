@@ -6,13 +6,16 @@ use anyhow::{bail, Result};
 use yosys_netlist_json as yosys;
 
 mod builder;
+mod cell_lib;
 pub mod gates;
 mod instance;
+mod rtl_cells;
 mod yosys_ext;
 
 pub use instance::{Instance, InstanceType};
 
 pub use builder::ModListBuilder;
+pub use cell_lib::CellLib;
 
 new_id!(InstanceId, InstanceVec, InstanceSlice);
 new_id!(WireId, WireVec, WireSlice);
@@ -37,6 +40,8 @@ pub struct Module {
     pub output_ports: OutputVec<ConnectionId>,
     pub port_is_input: ConnectionVec<bool>,
     pub wire_names: WireVec<Option<WireName>>,
+    /// 'matchi_glitch_free' annotation of the wires.
+    pub glitch_free: WireVec<Option<GlitchFree>>,
 }
 
 #[derive(Debug, Clone)]
@@ -59,11 +64,44 @@ pub struct WireProperties {
     pub sinks: Vec<(InstanceId, InputId)>,
 }
 
+/// Annotation that a wire is glitch-free (i.e., its value is stable during a clock cycle).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlitchFree {
+    /// The wire is assumed to be glitch-free.
+    Assume,
+    /// The wire is checked to be glitch-free.
+    Assert,
+}
+
+impl std::str::FromStr for GlitchFree {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "assume" => Self::Assume,
+            "assert" => Self::Assert,
+            _ => bail!(
+                "'{}' is not a valid 'matchi_glitch_free' annotation (expected 'assume' or 'assert').",
+                s
+            ),
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct WireGraph {
     pub graph: petgraph::Graph<WireId, ()>,
     pub node_indices: WireVec<petgraph::graph::NodeIndex>,
 }
+impl Module {
+    /// Human-readable name of a wire.
+    pub fn wire_name(&self, wire: WireId) -> String {
+        match &self.wire_names[wire] {
+            Some(wire_name) => wire_name.to_string(),
+            None => format!("<wire {}>", wire),
+        }
+    }
+}
+
 impl ModuleCombDeps {
     /// Add dependencies in comb_wire_dag and comb_input_deps to match worst-case inference based
     /// on gadget annotations.
@@ -0,0 +1,875 @@
+//! Bit-blasting of Yosys word-level (RTL) cells and of FPGA primitives into library gates.
+use super::gates::{Adder, CombBinary, CombUnitary, Dff, Gate, Latch, Lut};
+use super::{yosys_ext, ConnectionVec, Instance, InstanceType, WireId, WireName};
+use crate::WireValue;
+use anyhow::{bail, Context, Result};
+use yosys_netlist_json as yosys;
+
+/// Yosys word-level cells that can be bit-blasted.
+const RTL_CELLS: &[&str] = &[
+    "$not",
+    "$pos",
+    "$and",
+    "$or",
+    "$xor",
+    "$xnor",
+    "$mux",
+    "$dff",
+    "$dffe",
+    "$sdff",
+    "$sdffe",
+    "$dlatch",
+    "$eq",
+    "$ne",
+    "$reduce_and",
+    "$reduce_or",
+    "$reduce_xor",
+    "$reduce_xnor",
+    "$reduce_bool",
+    "$logic_not",
+    "$lut",
+    "$fa",
+];
+
+/// FPGA primitives (Xilinx and iCE40) that can be mapped to library gates.
+const FPGA_CELLS: &[&str] = &[
+    "LUT1",
+    "LUT2",
+    "LUT3",
+    "LUT4",
+    "LUT5",
+    "LUT6",
+    "FDRE",
+    "FDSE",
+    "FDCE",
+    "FDPE",
+    "SB_LUT4",
+    "SB_DFF",
+    "SB_DFFE",
+    "SB_DFFSR",
+    "SB_DFFSS",
+    "SB_DFFESR",
+    "SB_DFFESS",
+    "SB_DFFR",
+    "SB_DFFS",
+    "SB_DFFER",
+    "SB_DFFES",
+];
+
+/// The cell is not a library gate, but can be expanded into library gates.
+pub fn is_expanded_cell(cell_type: impl AsRef<str>) -> bool {
+    RTL_CELLS.contains(&cell_type.as_ref()) || FPGA_CELLS.contains(&cell_type.as_ref())
+}
+
+/// Wires created by the bit-blasting, which are numbered after the wires of the yosys module.
+#[derive(Debug, Clone)]
+pub struct NewWires {
+    first: usize,
+    names: Vec<WireName>,
+}
+
+impl NewWires {
+    pub fn new(n_wires: usize) -> Self {
+        Self {
+            first: n_wires,
+            names: vec![],
+        }
+    }
+    pub(super) fn new_wire(&mut self, name: String, offset: usize) -> WireId {
+        self.names.push(WireName::new(name, offset));
+        WireId::from_usize(self.first + self.names.len() - 1)
+    }
+    /// Total number of wires, including the yosys module ones.
+    pub fn n_wires(&self) -> usize {
+        self.first + self.names.len()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (WireId, &WireName)> {
+        self.names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (WireId::from_usize(self.first + i), name))
+    }
+}
+
+/// Builder for the gates of a bit-blasted cell.
+pub(super) struct CellExpander<'a> {
+    cell: &'a yosys::Cell,
+    cell_name: &'a str,
+    new_wires: &'a mut NewWires,
+    /// 'matchi_stop_glitches' annotation for the DFFs and latches.
+    stop_glitches: Option<bool>,
+    instances: Vec<Instance>,
+}
+
+impl<'a> CellExpander<'a> {
+    pub(super) fn new(
+        cell: &'a yosys::Cell,
+        cell_name: &'a str,
+        new_wires: &'a mut NewWires,
+        stop_glitches: Option<bool>,
+    ) -> Self {
+        Self {
+            cell,
+            cell_name,
+            new_wires,
+            stop_glitches,
+            instances: vec![],
+        }
+    }
+    pub(super) fn new_wire(&mut self, name: &str) -> WireId {
+        self.new_wires
+            .new_wire(format!("{}.{}", self.cell_name, name), 0)
+    }
+    pub(super) fn cell_name(&self) -> &str {
+        self.cell_name
+    }
+    pub(super) fn has_port(&self, port: &str) -> bool {
+        self.cell.connections.contains_key(port)
+    }
+    pub(super) fn into_instances(self) -> Vec<Instance> {
+        self.instances
+    }
+    fn param(&self, name: &str) -> Result<Option<usize>> {
+        self.cell
+            .parameters
+            .get(name)
+            .map(|param| {
+                param.to_number().with_context(|| {
+                    format!(
+                        "Parameter {} of cell {} is not a number.",
+                        name, self.cell_name
+                    )
+                })
+            })
+            .transpose()
+    }
+    fn port(&self, port: &str) -> Result<Vec<WireId>> {
+        let Some(bits) = self.cell.connections.get(port) else {
+            bail!("Cell {} has no connection {}.", self.cell_name, port);
+        };
+        bits.iter()
+            .map(|bit| WireId::try_from(*bit))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Invalid connection {} of cell {}.", port, self.cell_name))
+    }
+    pub(super) fn single_port(&self, port: &str) -> Result<WireId> {
+        match self.port(port)?.as_slice() {
+            [bit] => Ok(*bit),
+            _ => bail!(
+                "Port {} of cell {} must be a single bit.",
+                port,
+                self.cell_name
+            ),
+        }
+    }
+    /// Input port, sign- or zero-extended (or truncated) to width.
+    fn extended_port(&self, port: &str, width: usize) -> Result<Vec<WireId>> {
+        let mut bits = self.port(port)?;
+        let signed = self.param(&format!("{}_SIGNED", port))?.unwrap_or(0) != 0;
+        let ext = match bits.last() {
+            Some(msb) if signed => *msb,
+            _ => WireId::from_usize(0),
+        };
+        bits.resize(width, ext);
+        Ok(bits)
+    }
+    pub(super) fn gate(&mut self, name: String, gate: Gate, connections: Vec<WireId>) {
+        self.instances.push(Instance {
+            name,
+            architecture: InstanceType::Gate(gate),
+            connections: ConnectionVec::from_vec(connections),
+            stop_glitches: gate.is_sequential().then_some(self.stop_glitches).flatten(),
+        });
+    }
+    /// Reduce the bits with a chain of gates, the result is driven on output.
+    fn reduce(&mut self, bits: &[WireId], bgate: CombBinary, output: WireId) -> Result<()> {
+        let Some((first, others)) = bits.split_first() else {
+            bail!("Cell {} has an empty input.", self.cell_name);
+        };
+        if others.is_empty() {
+            self.gate(
+                format!("{}.reduce", self.cell_name),
+                Gate::CombUnitary(CombUnitary::Buf),
+                vec![*first, output],
+            );
+            return Ok(());
+        }
+        let mut acc = *first;
+        for (i, bit) in others.iter().enumerate() {
+            let out = if i + 1 == others.len() {
+                output
+            } else {
+                self.new_wires
+                    .new_wire(format!("{}.reduce", self.cell_name), i)
+            };
+            self.gate(
+                format!("{}.reduce[{}]", self.cell_name, i),
+                Gate::CombBinary(bgate),
+                vec![acc, *bit, out],
+            );
+            acc = out;
+        }
+        Ok(())
+    }
+    /// Drive the single-bit result of a reduction on Y (further bits of Y are 0), applying
+    /// reduce to compute it.
+    fn reduce_to_y(
+        &mut self,
+        inverted: bool,
+        reduce: impl FnOnce(&mut Self, WireId) -> Result<()>,
+    ) -> Result<()> {
+        let y = self.port("Y")?;
+        let Some((y0, y_others)) = y.split_first() else {
+            bail!("Cell {} has an empty output.", self.cell_name);
+        };
+        if inverted {
+            let res = self.new_wire("res");
+            reduce(self, res)?;
+            self.gate(
+                format!("{}.not", self.cell_name),
+                Gate::CombUnitary(CombUnitary::Not),
+                vec![res, *y0],
+            );
+        } else {
+            reduce(self, *y0)?;
+        }
+        for (i, y) in y_others.iter().enumerate() {
+            self.gate(
+                format!("{}[{}]", self.cell_name, i + 1),
+                Gate::CombUnitary(CombUnitary::Buf),
+                vec![WireId::from_usize(0), *y],
+            );
+        }
+        Ok(())
+    }
+    fn unary(&mut self, ugate: CombUnitary) -> Result<()> {
+        let y = self.port("Y")?;
+        let a = self.extended_port("A", y.len())?;
+        for (i, (a, y)) in a.into_iter().zip(y).enumerate() {
+            self.gate(
+                format!("{}[{}]", self.cell_name, i),
+                Gate::CombUnitary(ugate),
+                vec![a, y],
+            );
+        }
+        Ok(())
+    }
+    fn binary(&mut self, bgate: CombBinary) -> Result<()> {
+        let y = self.port("Y")?;
+        let a = self.extended_port("A", y.len())?;
+        let b = self.extended_port("B", y.len())?;
+        for (i, ((a, b), y)) in a.into_iter().zip(b).zip(y).enumerate() {
+            self.gate(
+                format!("{}[{}]", self.cell_name, i),
+                Gate::CombBinary(bgate),
+                vec![a, b, y],
+            );
+        }
+        Ok(())
+    }
+    fn mux(&mut self) -> Result<()> {
+        let a = self.port("A")?;
+        let b = self.port("B")?;
+        let s = self.port("S")?;
+        let y = self.port("Y")?;
+        if s.len() != 1 || a.len() != y.len() || b.len() != y.len() {
+            bail!("Invalid port widths for $mux cell {}.", self.cell_name);
+        }
+        for (i, ((a, b), y)) in a.into_iter().zip(b).zip(y).enumerate() {
+            self.gate(
+                format!("{}[{}]", self.cell_name, i),
+                Gate::Mux { inverted: false },
+                vec![a, b, s[0], y],
+            );
+        }
+        Ok(())
+    }
+    /// Bitwise full adder, with sum Y and carry X.
+    fn full_adder(&mut self) -> Result<()> {
+        let a = self.port("A")?;
+        let b = self.port("B")?;
+        let c = self.port("C")?;
+        let x = self.port("X")?;
+        let y = self.port("Y")?;
+        if [&b, &c, &x, &y].iter().any(|port| port.len() != a.len()) {
+            bail!("Invalid port widths for $fa cell {}.", self.cell_name);
+        }
+        for (i, ((((a, b), c), x), y)) in a.into_iter().zip(b).zip(c).zip(x).zip(y).enumerate() {
+            self.gate(
+                format!("{}[{}]", self.cell_name, i),
+                Gate::Adder(Adder::Full),
+                vec![a, b, c, y, x],
+            );
+        }
+        Ok(())
+    }
+    /// Polarity parameter (1 for active-high, 0 for active-low).
+    fn polarity(&self, name: &str) -> Result<WireValue> {
+        Ok(WireValue::from(self.param(name)?.unwrap_or(1) != 0))
+    }
+    /// Bit of a constant-valued parameter (e.g., reset value).
+    fn param_bit(&self, name: &str, bit: usize) -> Result<WireValue> {
+        let Some(param) = self.cell.parameters.get(name) else {
+            bail!("Cell {} has no parameter {}.", self.cell_name, name);
+        };
+        Ok(WireValue::from(match param {
+            yosys::AttributeVal::N(x) => x.checked_shr(bit as u32).is_some_and(|x| x & 1 == 1),
+            yosys::AttributeVal::S(x) => x.chars().rev().nth(bit) == Some('1'),
+        }))
+    }
+    pub(super) fn dff_gate(
+        &mut self,
+        name: String,
+        clk: WireId,
+        d: WireId,
+        q: WireId,
+        enable: Option<(WireId, WireValue)>,
+        reset: Option<(WireId, WireValue, WireValue)>,
+    ) {
+        let connections = [clk, d]
+            .into_iter()
+            .chain(enable.map(|(en, _)| en))
+            .chain(reset.map(|(srst, _, _)| srst))
+            .chain([q])
+            .collect();
+        let dff = Dff {
+            enable: enable.map(|(_, polarity)| polarity),
+            reset: reset.map(|(_, polarity, value)| (polarity, value)),
+        };
+        self.gate(name, Gate::Dff(dff), connections);
+    }
+    fn dff(&mut self, has_enable: bool, has_reset: bool) -> Result<()> {
+        if self.polarity("CLK_POLARITY")? != WireValue::_1 {
+            bail!(
+                "Negative-edge DFFs are not supported (cell {}).",
+                self.cell_name
+            );
+        }
+        let clk = self.single_port("CLK")?;
+        let d = self.port("D")?;
+        let q = self.port("Q")?;
+        if d.len() != q.len() {
+            bail!(
+                "Invalid port widths for {} cell {}.",
+                self.cell.cell_type,
+                self.cell_name
+            );
+        }
+        let enable = if has_enable {
+            Some((self.single_port("EN")?, self.polarity("EN_POLARITY")?))
+        } else {
+            None
+        };
+        let srst = if has_reset {
+            Some((self.single_port("SRST")?, self.polarity("SRST_POLARITY")?))
+        } else {
+            None
+        };
+        for (i, (d, q)) in d.into_iter().zip(q).enumerate() {
+            let reset = match srst {
+                Some((srst, polarity)) => Some((srst, polarity, self.param_bit("SRST_VALUE", i)?)),
+                None => None,
+            };
+            self.dff_gate(
+                format!("{}[{}]", self.cell_name, i),
+                clk,
+                d,
+                q,
+                enable,
+                reset,
+            );
+        }
+        Ok(())
+    }
+    fn latch(&mut self) -> Result<()> {
+        let en = self.single_port("EN")?;
+        let d = self.port("D")?;
+        let q = self.port("Q")?;
+        if d.len() != q.len() {
+            bail!("Invalid port widths for $dlatch cell {}.", self.cell_name);
+        }
+        let enable = self.polarity("EN_POLARITY")?;
+        for (i, (d, q)) in d.into_iter().zip(q).enumerate() {
+            self.gate(
+                format!("{}[{}]", self.cell_name, i),
+                Gate::Latch(Latch { enable }),
+                vec![en, d, q],
+            );
+        }
+        Ok(())
+    }
+    /// Inversion parameter of a port of a vendor primitive (e.g., IS_R_INVERTED).
+    fn port_inverted(&self, port: &str) -> Result<bool> {
+        Ok(self.param(&format!("IS_{}_INVERTED", port))?.unwrap_or(0) != 0)
+    }
+    /// Single-bit vendor flip-flop (ports C, D and Q), with an optional active-high enable and
+    /// optional synchronous reset (port name and reset value).
+    /// If reset_needs_enable, the reset only happens when the flip-flop is enabled (iCE40),
+    /// otherwise it has priority over the enable (Xilinx).
+    fn vendor_dff(
+        &mut self,
+        enable: Option<&str>,
+        reset: Option<(&str, WireValue)>,
+        reset_needs_enable: bool,
+    ) -> Result<()> {
+        if self.port_inverted("C")? || self.port_inverted("D")? {
+            bail!(
+                "Inverted clock or data inputs are not supported (cell {}).",
+                self.cell_name
+            );
+        }
+        let clk = self.single_port("C")?;
+        let mut d = self.single_port("D")?;
+        let q = self.single_port("Q")?;
+        let enable = match enable {
+            Some(port) => Some((self.single_port(port)?, WireValue::_1)),
+            None => None,
+        };
+        let reset = match reset {
+            Some((port, value)) => {
+                let polarity = WireValue::from(!self.port_inverted(port)?);
+                Some((self.single_port(port)?, polarity, value))
+            }
+            None => None,
+        };
+        let reset = match reset {
+            Some((srst, WireValue::_1, value)) if reset_needs_enable => {
+                // Same as a DFF with enable, whose input is forced to the reset value.
+                let bgate = match value {
+                    WireValue::_0 => CombBinary::AndNot,
+                    WireValue::_1 => CombBinary::Or,
+                };
+                let out = self.new_wire("reset");
+                self.gate(
+                    format!("{}.reset", self.cell_name),
+                    Gate::CombBinary(bgate),
+                    vec![d, srst, out],
+                );
+                d = out;
+                None
+            }
+            reset => reset,
+        };
+        self.dff_gate(self.cell_name.to_owned(), clk, d, q, enable, reset);
+        Ok(())
+    }
+    /// LUT whose truth table is given by a parameter (bit i is the output for inputs i).
+    fn lut(&mut self, inputs: Vec<WireId>, output: WireId, table_param: &str) -> Result<()> {
+        let n_inputs = inputs.len();
+        if n_inputs > Lut::MAX_INPUTS {
+            bail!(
+                "LUTs with more than {} inputs are not supported (cell {}).",
+                Lut::MAX_INPUTS,
+                self.cell_name
+            );
+        }
+        let mut table = 0;
+        for i in 0..(1 << n_inputs) {
+            if self.param_bit(table_param, i)? == WireValue::_1 {
+                table |= 1 << i;
+            }
+        }
+        self.gate(
+            self.cell_name.to_owned(),
+            Gate::Lut(Lut::new(n_inputs, table)?),
+            [output].into_iter().chain(inputs).collect(),
+        );
+        Ok(())
+    }
+    fn yosys_lut(&mut self) -> Result<()> {
+        let inputs = self.port("A")?;
+        let output = self.single_port("Y")?;
+        self.lut(inputs, output, "LUT")
+    }
+    /// LUT with single-bit inputs I0, I1, etc., and output O.
+    fn vendor_lut(&mut self, n_inputs: usize, table_param: &str) -> Result<()> {
+        let inputs = (0..n_inputs)
+            .map(|i| self.single_port(&format!("I{}", i)))
+            .collect::<Result<Vec<_>>>()?;
+        let output = self.single_port("O")?;
+        self.lut(inputs, output, table_param)
+    }
+    /// Bitwise comparison of A and B, then reduction.
+    fn compare(&mut self, bgate: CombBinary, reduce: CombBinary) -> Result<()> {
+        let width = self.port("A")?.len().max(self.port("B")?.len());
+        let a = self.extended_port("A", width)?;
+        let b = self.extended_port("B", width)?;
+        let bits = a
+            .into_iter()
+            .zip(b)
+            .enumerate()
+            .map(|(i, (a, b))| {
+                let out = self
+                    .new_wires
+                    .new_wire(format!("{}.cmp", self.cell_name), i);
+                self.gate(
+                    format!("{}.cmp[{}]", self.cell_name, i),
+                    Gate::CombBinary(bgate),
+                    vec![a, b, out],
+                );
+                out
+            })
+            .collect::<Vec<_>>();
+        self.reduce_to_y(false, |this, y| this.reduce(&bits, reduce, y))
+    }
+    fn reduction(&mut self, reduce: CombBinary, inverted: bool) -> Result<()> {
+        let a = self.port("A")?;
+        self.reduce_to_y(inverted, |this, y| this.reduce(&a, reduce, y))
+    }
+}
+
+/// Bit-blast a word-level cell into library gates, creating new internal wires if needed.
+/// module_stop_glitches is the 'matchi_stop_glitches' annotation of the parent module.
+pub fn expand_cell(
+    cell: &yosys::Cell,
+    cell_name: &str,
+    module_stop_glitches: Option<bool>,
+    new_wires: &mut NewWires,
+) -> Result<Vec<Instance>> {
+    let cell_stop_glitches = yosys_ext::stop_glitches_attr(&cell.attributes)?;
+    let mut expander = CellExpander::new(
+        cell,
+        cell_name,
+        new_wires,
+        cell_stop_glitches.or(module_stop_glitches),
+    );
+    match cell.cell_type.as_str() {
+        "$not" => expander.unary(CombUnitary::Not),
+        "$pos" => expander.unary(CombUnitary::Buf),
+        "$and" => expander.binary(CombBinary::And),
+        "$or" => expander.binary(CombBinary::Or),
+        "$xor" => expander.binary(CombBinary::Xor),
+        "$xnor" => expander.binary(CombBinary::Xnor),
+        "$mux" => expander.mux(),
+        "$fa" => expander.full_adder(),
+        "$dff" => expander.dff(false, false),
+        "$dffe" => expander.dff(true, false),
+        "$sdff" => expander.dff(false, true),
+        "$sdffe" => expander.dff(true, true),
+        "$dlatch" => expander.latch(),
+        "$eq" => expander.compare(CombBinary::Xnor, CombBinary::And),
+        "$ne" => expander.compare(CombBinary::Xor, CombBinary::Or),
+        "$reduce_and" => expander.reduction(CombBinary::And, false),
+        "$reduce_or" | "$reduce_bool" => expander.reduction(CombBinary::Or, false),
+        "$reduce_xor" => expander.reduction(CombBinary::Xor, false),
+        "$reduce_xnor" => expander.reduction(CombBinary::Xor, true),
+        "$logic_not" => expander.reduction(CombBinary::Or, true),
+        "$lut" => expander.yosys_lut(),
+        // Xilinx primitives.
+        "LUT1" => expander.vendor_lut(1, "INIT"),
+        "LUT2" => expander.vendor_lut(2, "INIT"),
+        "LUT3" => expander.vendor_lut(3, "INIT"),
+        "LUT4" => expander.vendor_lut(4, "INIT"),
+        "LUT5" => expander.vendor_lut(5, "INIT"),
+        "LUT6" => expander.vendor_lut(6, "INIT"),
+        "FDRE" => expander.vendor_dff(Some("CE"), Some(("R", WireValue::_0)), false),
+        "FDSE" => expander.vendor_dff(Some("CE"), Some(("S", WireValue::_1)), false),
+        // iCE40 primitives.
+        "SB_LUT4" => expander.vendor_lut(4, "LUT_INIT"),
+        "SB_DFF" => expander.vendor_dff(None, None, false),
+        "SB_DFFE" => expander.vendor_dff(Some("E"), None, false),
+        "SB_DFFSR" => expander.vendor_dff(None, Some(("R", WireValue::_0)), false),
+        "SB_DFFSS" => expander.vendor_dff(None, Some(("S", WireValue::_1)), false),
+        "SB_DFFESR" => expander.vendor_dff(Some("E"), Some(("R", WireValue::_0)), true),
+        "SB_DFFESS" => expander.vendor_dff(Some("E"), Some(("S", WireValue::_1)), true),
+        "FDCE" | "FDPE" | "SB_DFFR" | "SB_DFFS" | "SB_DFFER" | "SB_DFFES" => Err(anyhow::anyhow!(
+            "Flip-flops with asynchronous reset are not supported."
+        )),
+        _ => unreachable!("{} is not an expanded cell.", cell.cell_type),
+    }
+    .with_context(|| format!("Could not bit-blast cell {}.", cell_name))?;
+    if cell_stop_glitches.is_some()
+        && !expander.instances.iter().any(
+            |inst| matches!(&inst.architecture, InstanceType::Gate(gate) if gate.is_sequential()),
+        )
+    {
+        bail!(
+            "Attribute 'matchi_stop_glitches' is given on cell {}, which is not sequential.",
+            cell_name
+        );
+    }
+    Ok(expander.into_instances())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Yosys cell, with parameters and connections as in the JSON netlist.
+    fn cell(
+        cell_type: &str,
+        parameters: serde_json::Value,
+        connections: serde_json::Value,
+    ) -> yosys::Cell {
+        serde_json::from_value(serde_json::json!({
+            "hide_name": 0,
+            "type": cell_type,
+            "parameters": parameters,
+            "attributes": {},
+            "port_directions": {},
+            "connections": connections,
+        }))
+        .unwrap()
+    }
+    /// Gates of the expanded cell, with the wires of their connections. The new wires are
+    /// numbered from 100.
+    fn expand(cell: &yosys::Cell) -> Result<Vec<(Gate, Vec<usize>)>> {
+        let mut new_wires = NewWires::new(100);
+        Ok(expand_cell(cell, "c", None, &mut new_wires)?
+            .into_iter()
+            .map(|instance| {
+                let InstanceType::Gate(gate) = instance.architecture else {
+                    panic!("{} is not a gate.", instance.name);
+                };
+                let wires = instance.connections.iter().map(|w| w.index()).collect();
+                (gate, wires)
+            })
+            .collect())
+    }
+    fn bgate(bgate: CombBinary, wires: &[usize]) -> (Gate, Vec<usize>) {
+        (Gate::CombBinary(bgate), wires.to_vec())
+    }
+    fn ugate(ugate: CombUnitary, wires: &[usize]) -> (Gate, Vec<usize>) {
+        (Gate::CombUnitary(ugate), wires.to_vec())
+    }
+    fn lut(n_inputs: usize, table: u64, wires: &[usize]) -> (Gate, Vec<usize>) {
+        (
+            Gate::Lut(Lut::new(n_inputs, table).unwrap()),
+            wires.to_vec(),
+        )
+    }
+    fn dff(enable: Option<WireValue>, reset: Option<(WireValue, WireValue)>) -> Gate {
+        Gate::Dff(Dff { enable, reset })
+    }
+
+    #[test]
+    fn compare() {
+        let eq = cell(
+            "$eq",
+            serde_json::json!({}),
+            serde_json::json!({"A": [2, 3], "B": [4, 5], "Y": [6]}),
+        );
+        assert_eq!(
+            expand(&eq).unwrap(),
+            vec![
+                bgate(CombBinary::Xnor, &[2, 4, 100]),
+                bgate(CombBinary::Xnor, &[3, 5, 101]),
+                bgate(CombBinary::And, &[100, 101, 6]),
+            ]
+        );
+        // B is zero-extended, and the upper bits of Y are 0.
+        let ne = cell(
+            "$ne",
+            serde_json::json!({}),
+            serde_json::json!({"A": [2, 3], "B": [4], "Y": [6, 7]}),
+        );
+        assert_eq!(
+            expand(&ne).unwrap(),
+            vec![
+                bgate(CombBinary::Xor, &[2, 4, 100]),
+                bgate(CombBinary::Xor, &[3, 0, 101]),
+                bgate(CombBinary::Or, &[100, 101, 6]),
+                ugate(CombUnitary::Buf, &[0, 7]),
+            ]
+        );
+        // Signed B is sign-extended.
+        let ne = cell(
+            "$ne",
+            serde_json::json!({"A_SIGNED": 1, "B_SIGNED": 1}),
+            serde_json::json!({"A": [2, 3], "B": [4], "Y": [6]}),
+        );
+        assert_eq!(
+            expand(&ne).unwrap()[1],
+            bgate(CombBinary::Xor, &[3, 4, 101])
+        );
+    }
+
+    #[test]
+    fn reductions() {
+        let reduce = |cell_type, a: &[usize], y: &[usize]| {
+            expand(&cell(
+                cell_type,
+                serde_json::json!({}),
+                serde_json::json!({"A": a, "Y": y}),
+            ))
+            .unwrap()
+        };
+        assert_eq!(
+            reduce("$reduce_and", &[2, 3, 4], &[5]),
+            vec![
+                bgate(CombBinary::And, &[2, 3, 100]),
+                bgate(CombBinary::And, &[100, 4, 5]),
+            ]
+        );
+        assert_eq!(
+            reduce("$reduce_xnor", &[2, 3], &[5]),
+            vec![
+                bgate(CombBinary::Xor, &[2, 3, 100]),
+                ugate(CombUnitary::Not, &[100, 5]),
+            ]
+        );
+        assert_eq!(
+            reduce("$logic_not", &[2, 3], &[5]),
+            vec![
+                bgate(CombBinary::Or, &[2, 3, 100]),
+                ugate(CombUnitary::Not, &[100, 5]),
+            ]
+        );
+        assert_eq!(
+            reduce("$reduce_bool", &[2], &[5, 6]),
+            vec![
+                ugate(CombUnitary::Buf, &[2, 5]),
+                ugate(CombUnitary::Buf, &[0, 6]),
+            ]
+        );
+        assert!(expand(&cell(
+            "$reduce_or",
+            serde_json::json!({}),
+            serde_json::json!({"A": [], "Y": [5]}),
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn mux() {
+        let mux = cell(
+            "$mux",
+            serde_json::json!({"WIDTH": 2}),
+            serde_json::json!({"A": [2, 3], "B": [4, 5], "S": [6], "Y": [7, 8]}),
+        );
+        assert_eq!(
+            expand(&mux).unwrap(),
+            vec![
+                (Gate::Mux { inverted: false }, vec![2, 4, 6, 7]),
+                (Gate::Mux { inverted: false }, vec![3, 5, 6, 8]),
+            ]
+        );
+        let mux = cell(
+            "$mux",
+            serde_json::json!({"WIDTH": 1}),
+            serde_json::json!({"A": [2], "B": [4], "S": [6, 7], "Y": [8]}),
+        );
+        assert!(expand(&mux).is_err());
+    }
+
+    #[test]
+    fn lut_init_bit_order() {
+        // Bit i of the INIT is the output for the inputs i, with I0 as least significant bit.
+        let sb_lut4 = cell(
+            "SB_LUT4",
+            serde_json::json!({"LUT_INIT": "0000000000000010"}),
+            serde_json::json!({"I0": [2], "I1": [3], "I2": [4], "I3": [5], "O": [6]}),
+        );
+        assert_eq!(
+            expand(&sb_lut4).unwrap(),
+            vec![lut(4, 0b10, &[6, 2, 3, 4, 5])]
+        );
+        let lut6 = cell(
+            "LUT6",
+            serde_json::json!({"INIT": format!("1{}", "0".repeat(63))}),
+            serde_json::json!({
+                "I0": [2], "I1": [3], "I2": [4], "I3": [5], "I4": [6], "I5": [7], "O": [8]
+            }),
+        );
+        assert_eq!(
+            expand(&lut6).unwrap(),
+            vec![lut(6, 1 << 63, &[8, 2, 3, 4, 5, 6, 7])]
+        );
+        let lut2 = cell(
+            "LUT2",
+            serde_json::json!({"INIT": 0b0100}),
+            serde_json::json!({"I0": [2], "I1": [3], "O": [4]}),
+        );
+        assert_eq!(expand(&lut2).unwrap(), vec![lut(2, 0b0100, &[4, 2, 3])]);
+    }
+
+    #[test]
+    fn vendor_dffs() {
+        let ports =
+            serde_json::json!({"C": [2], "D": [3], "CE": [4], "R": [5], "S": [5], "Q": [6]});
+        // Xilinx: the reset has priority over the enable, FDRE resets to 0 and FDSE to 1.
+        assert_eq!(
+            expand(&cell("FDRE", serde_json::json!({}), ports.clone())).unwrap(),
+            vec![(
+                dff(Some(WireValue::_1), Some((WireValue::_1, WireValue::_0))),
+                vec![2, 3, 4, 5, 6]
+            )]
+        );
+        assert_eq!(
+            expand(&cell("FDSE", serde_json::json!({}), ports.clone())).unwrap(),
+            vec![(
+                dff(Some(WireValue::_1), Some((WireValue::_1, WireValue::_1))),
+                vec![2, 3, 4, 5, 6]
+            )]
+        );
+        assert_eq!(
+            expand(&cell(
+                "FDRE",
+                serde_json::json!({"IS_R_INVERTED": 1}),
+                ports.clone()
+            ))
+            .unwrap(),
+            vec![(
+                dff(Some(WireValue::_1), Some((WireValue::_0, WireValue::_0))),
+                vec![2, 3, 4, 5, 6]
+            )]
+        );
+        assert!(expand(&cell(
+            "FDRE",
+            serde_json::json!({"IS_C_INVERTED": 1}),
+            ports
+        ))
+        .is_err());
+        // iCE40: the reset needs the enable, it is mapped on the data input.
+        let sb_dffesr = cell(
+            "SB_DFFESR",
+            serde_json::json!({}),
+            serde_json::json!({"C": [2], "D": [3], "E": [4], "R": [5], "Q": [6]}),
+        );
+        assert_eq!(
+            expand(&sb_dffesr).unwrap(),
+            vec![
+                bgate(CombBinary::AndNot, &[3, 5, 100]),
+                (dff(Some(WireValue::_1), None), vec![2, 100, 4, 6]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rtl_dffs() {
+        let sdffe = cell(
+            "$sdffe",
+            serde_json::json!({
+                "CLK_POLARITY": 1, "EN_POLARITY": 0, "SRST_POLARITY": 1, "SRST_VALUE": "10"
+            }),
+            serde_json::json!({"CLK": [2], "D": [3, 4], "EN": [5], "SRST": [6], "Q": [7, 8]}),
+        );
+        assert_eq!(
+            expand(&sdffe).unwrap(),
+            vec![
+                (
+                    dff(Some(WireValue::_0), Some((WireValue::_1, WireValue::_0))),
+                    vec![2, 3, 5, 6, 7]
+                ),
+                (
+                    dff(Some(WireValue::_0), Some((WireValue::_1, WireValue::_1))),
+                    vec![2, 4, 5, 6, 8]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_dffs() {
+        let negedge = cell(
+            "$dff",
+            serde_json::json!({"CLK_POLARITY": 0}),
+            serde_json::json!({"CLK": [2], "D": [3], "Q": [4]}),
+        );
+        assert!(expand(&negedge).is_err());
+        let ports = serde_json::json!({"C": [2], "D": [3], "CE": [4], "CLR": [5], "Q": [6]});
+        assert!(expand(&cell("FDCE", serde_json::json!({}), ports)).is_err());
+        let ports = serde_json::json!({"C": [2], "D": [3], "R": [5], "Q": [6]});
+        assert!(expand(&cell("SB_DFFR", serde_json::json!({}), ports)).is_err());
+    }
+}
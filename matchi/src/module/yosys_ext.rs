@@ -1,10 +1,12 @@
+use super::cell_lib::CellLib;
+use super::rtl_cells::{self, NewWires};
 use super::ModList;
 use super::{
-    ConnectionId, ConnectionVec, Instance, InstanceType, Ports, WireId, WireName, WireProperties,
-    WireVec,
+    ConnectionId, ConnectionVec, GlitchFree, Instance, InstanceType, Ports, WireId, WireName,
+    WireProperties, WireVec,
 };
 use crate::WireValue;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use yosys_netlist_json as yosys;
 
 pub fn ports(yosys_module: &yosys::Module, clock: Option<WireId>) -> (Ports, Option<WireName>) {
@@ -39,17 +41,53 @@ pub fn filter_ports(
         .collect::<Vec<_>>()
 }
 
+/// Instances for the cells of the module, cells of the cell library, word-level cells and FPGA
+/// primitives are expanded (possibly creating new wires).
 pub fn module_instances(
     yosys_module: &yosys::Module,
     netlist: &impl ModList,
+    cell_lib: &CellLib,
+    new_wires: &mut NewWires,
 ) -> Result<Vec<Instance>> {
+    let module_stop_glitches = stop_glitches_attr(&yosys_module.attributes)?;
     // Sort cells for reproducibility.
     let mut module_cells: Vec<_> = yosys_module.cells.iter().collect();
     module_cells.sort_unstable_by_key(|&(name, _cell)| name);
-    module_cells
-        .into_iter()
-        .map(|(cell_name, cell)| Instance::from_cell(cell, cell_name, netlist))
-        .collect()
+    let mut res = vec![];
+    for (cell_name, cell) in module_cells {
+        if cell_lib.contains(&cell.cell_type) {
+            res.extend(cell_lib.expand_cell(cell, cell_name, module_stop_glitches, new_wires)?);
+        } else if rtl_cells::is_expanded_cell(&cell.cell_type) {
+            res.extend(rtl_cells::expand_cell(
+                cell,
+                cell_name,
+                module_stop_glitches,
+                new_wires,
+            )?);
+        } else {
+            res.push(Instance::from_cell(
+                cell,
+                cell_name,
+                netlist,
+                module_stop_glitches,
+            )?);
+        }
+    }
+    Ok(res)
+}
+
+/// Parse the 'matchi_stop_glitches' attribute (0 or 1).
+pub fn stop_glitches_attr(
+    attributes: &std::collections::HashMap<String, yosys::AttributeVal>,
+) -> Result<Option<bool>> {
+    attributes
+        .get("matchi_stop_glitches")
+        .map(|attr| match attr.to_number() {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            _ => bail!("Attribute 'matchi_stop_glitches' must be 0 or 1."),
+        })
+        .transpose()
 }
 
 pub fn tie_instances() -> impl Iterator<Item = Instance> {
@@ -60,11 +98,13 @@ pub fn tie_instances() -> impl Iterator<Item = Instance> {
             name: "TIELO".to_owned(),
             architecture: InstanceType::Tie(WireValue::_0),
             connections: ConnectionVec::from_vec(vec![WireId::from_usize(0)]),
+            stop_glitches: None,
         },
         Instance {
             name: "TIEHI".to_owned(),
             architecture: InstanceType::Tie(WireValue::_1),
             connections: ConnectionVec::from_vec(vec![WireId::from_usize(1)]),
+            stop_glitches: None,
         },
     ]
     .into_iter()
@@ -157,6 +197,7 @@ pub fn wires_output_connection(
 pub fn wire_names(
     module: &yosys::Module,
     wires: &WireVec<WireProperties>,
+    new_wires: &NewWires,
 ) -> WireVec<Option<WireName>> {
     let mut res = WireVec::from_vec(vec![None; wires.len()]);
     for (name, netname) in module.netnames.iter() {
@@ -165,10 +206,39 @@ pub fn wire_names(
             res[wire_id] = Some(WireName::new(name.clone(), offset));
         }
     }
+    for (wire_id, wire_name) in new_wires.iter() {
+        res[wire_id] = Some(wire_name.clone());
+    }
     res
 }
 
-pub fn wire_name(module: &yosys::Module, wire: WireId) -> WireName {
+/// Parse the 'matchi_glitch_free' attributes of the wires.
+pub fn wires_glitch_free(
+    module: &yosys::Module,
+    n_wires: usize,
+) -> Result<WireVec<Option<GlitchFree>>> {
+    let mut res = WireVec::from_vec(vec![None; n_wires]);
+    for (name, netname) in module.netnames.iter() {
+        let Some(attr) = netname.attributes.get("matchi_glitch_free") else {
+            continue;
+        };
+        let yosys::AttributeVal::S(attr) = attr else {
+            bail!(
+                "Attribute 'matchi_glitch_free' of wire {} must be a string.",
+                name
+            );
+        };
+        let glitch_free = attr.parse::<GlitchFree>()?;
+        for bitval in netname.bits.iter() {
+            if let Ok(wire_id) = WireId::try_from(*bitval) {
+                res[wire_id] = Some(glitch_free);
+            }
+        }
+    }
+    Ok(res)
+}
+
+pub fn wire_name(module: &yosys::Module, new_wires: &NewWires, wire: WireId) -> WireName {
     if wire == WireId::from_raw(0) {
         return WireName::new("TIELO".to_owned(), 0);
     }
@@ -183,5 +253,8 @@ pub fn wire_name(module: &yosys::Module, wire: WireId) -> WireName {
             }
         }
     }
-    unreachable!("No netname for wire id {}", wire);
+    new_wires
+        .iter()
+        .find_map(|(wire_id, wire_name)| (wire_id == wire).then(|| wire_name.clone()))
+        .unwrap_or_else(|| unreachable!("No netname for wire id {}", wire))
 }
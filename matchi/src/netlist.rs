@@ -1,6 +1,6 @@
 use super::gadget::PipelineGadget;
-use super::gadget::TopGadget;
-use super::module::{Module, ModuleCombDeps};
+use super::gadget::{LoopyGadget, TopGadget};
+use super::module::{InstanceType, Module, ModuleCombDeps};
 use super::{ModuleId, ModuleVec};
 use fnv::FnvHashMap as HashMap;
 use yosys_netlist_json as yosys;
@@ -43,13 +43,18 @@ pub struct Netlist {
     modules: ModuleVec<Module>,
     module_comb_deps: ModuleVec<ModuleCombDeps>,
     gadgets: ModuleVec<Option<PipelineGadget>>,
+    loopy_gadgets: ModuleVec<Option<LoopyGadget>>,
     names: HashMap<String, ModuleId>,
     pub top_gadget: TopGadget,
 }
 
 impl Netlist {
-    pub fn new(netlist: &yosys::Netlist, top_gadget: &str) -> Result<Self> {
-        let builder = super::module::ModListBuilder::new(netlist)?;
+    pub fn new(
+        netlist: &yosys::Netlist,
+        top_gadget: &str,
+        cell_lib: &super::module::CellLib,
+    ) -> Result<Self> {
+        let builder = super::module::ModListBuilder::new(netlist, cell_lib)?;
         let Some(top_gadget_id) = builder.id_of(top_gadget) else {
             bail!("Top module {top_gadget} not found in the netlist.");
         };
@@ -59,6 +64,7 @@ impl Netlist {
             })?;
         Ok(Netlist {
             gadgets: builder.gadgets,
+            loopy_gadgets: builder.loopy_gadgets,
             modules: builder.modules,
             module_comb_deps: builder.module_comb_deps,
             names: builder.names,
@@ -68,5 +74,43 @@ impl Netlist {
     pub fn gadget(&self, module_id: ModuleId) -> Option<&PipelineGadget> {
         self.gadgets[module_id].as_ref()
     }
+    /// All pipeline gadgets, sub-gadgets come before the gadgets that instantiate them.
+    pub fn gadgets(&self) -> impl Iterator<Item = &PipelineGadget> {
+        self.gadgets.iter().flatten()
+    }
+    pub fn loopy_gadget(&self, module_id: ModuleId) -> Option<&LoopyGadget> {
+        self.loopy_gadgets[module_id].as_ref()
+    }
+    /// All loopy gadgets with the 'isolate' strategy, with their module.
+    pub fn loopy_gadgets(&self) -> impl Iterator<Item = (ModuleId, &LoopyGadget)> {
+        self.loopy_gadgets
+            .iter_enumerated()
+            .filter_map(|(module_id, gadget)| Some((module_id, gadget.as_ref()?)))
+    }
+    /// Instance paths (relative to the top-level gadget) of all the instances of a module.
+    pub fn instance_paths(&self, module_id: ModuleId) -> Vec<Vec<String>> {
+        let mut res = vec![];
+        self.find_instances(self.top_gadget.module_id, module_id, &mut vec![], &mut res);
+        res
+    }
+    fn find_instances(
+        &self,
+        parent_id: ModuleId,
+        module_id: ModuleId,
+        path: &mut Vec<String>,
+        res: &mut Vec<Vec<String>>,
+    ) {
+        for instance in &self.module(parent_id).instances {
+            if let InstanceType::Module(submodule_id) = instance.architecture {
+                path.push(instance.name.clone());
+                if submodule_id == module_id {
+                    res.push(path.clone());
+                } else {
+                    self.find_instances(submodule_id, module_id, path, res);
+                }
+                path.pop();
+            }
+        }
+    }
     // TODO: build "packed modules" that include Module, ModuleCombDeps and Option<PipelineGadget>.
 }
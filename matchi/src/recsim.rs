@@ -25,20 +25,22 @@
 // wire's fanout.
 // Actual checking is done in `check_safe_out` for gates, and `check_safe_input` for pipeline
 // gadgets.
+use super::gadget::top::ActiveWireVec;
 use super::gadget::{Latency, LatencyVec, PortRole};
-use super::module::gates::{CombUnitary, Gate};
+use super::module::gates::{CombUnitary, Dff, Gate, Latch};
 use super::module::{
-    ConnectionId, InputId, InputVec, InstanceId, InstanceType, InstanceVec, OutputId, WireId,
-    WireVec,
+    ConnectionId, GlitchFree, InputId, InputVec, Instance, InstanceId, InstanceType, InstanceVec,
+    OutputId, WireId, WireVec,
 };
 use super::netlist::{ModList, Netlist};
 use super::simulation::{NspgiDep, WireState};
 use super::top_sim::GlobSimulationState;
 use super::{ModuleId, WireValue};
+use crate::diagnostics::{Violation, ViolationKind};
 use crate::share_set::ShareSet;
 use crate::top_sim::GadgetExecCycle;
 use crate::type_utils::new_id;
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{bail, Context, Result};
 use itertools::izip;
 
 // Globally-unique instance ID.
@@ -124,6 +126,12 @@ pub trait Evaluator {
     fn glob_inst2path(&self, ginst: GlobInstId, netlist: &Netlist) -> Option<String> {
         None
     }
+    /// State elements (DFFs and pipeline gadget stages) that store a share-sensitive value at the
+    /// end of the current cycle.
+    #[allow(unused_variables)]
+    fn sensitive_state(&self, state: &EvaluatorState, netlist: &Netlist) -> Vec<SensitiveState> {
+        vec![]
+    }
     fn debug_state(&self, state: &EvaluatorState, netlist: &Netlist);
 }
 
@@ -134,6 +142,7 @@ enum InstanceEvaluator {
     Tie(TieEvaluator),
     Module(ModuleEvaluator),
     Gadget(PipelineGadgetEvaluator),
+    LoopyGadget(LoopyGadgetEvaluator),
 }
 
 #[derive(Debug, Clone)]
@@ -151,6 +160,8 @@ pub struct ModuleEvaluator {
 struct GateEvaluator {
     gate: Gate,
     inst_id: GlobInstId,
+    /// For DFFs and latches, the stored value does not glitch on the output.
+    stop_glitches: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -165,11 +176,21 @@ struct PipelineGadgetEvaluator {
     module_evaluator: ModuleEvaluator,
 }
 
+/// Evaluator for the instances of a loopy gadget with the 'isolate' strategy: the gadget is
+/// verified on its own, hence its content is only simulated, and its inputs are checked against
+/// its annotations.
+#[derive(Debug, Clone)]
+struct LoopyGadgetEvaluator {
+    module_id: ModuleId,
+    module_evaluator: ModuleEvaluator,
+}
+
 #[derive(Debug, Clone)]
 pub enum EvaluatorState {
     Gate(GateState),
     Module(ModuleState),
     PipelineGadget(PipelineGadgetState),
+    LoopyGadget(LoopyGadgetState),
     Tie,
 }
 
@@ -192,6 +213,7 @@ impl EvaluatorState {
         match self {
             Self::Module(res) => Some(res),
             Self::PipelineGadget(res) => Some(&res.module_state),
+            Self::LoopyGadget(res) => Some(&res.module_state),
             _ => None,
         }
     }
@@ -223,6 +245,20 @@ impl EvaluatorState {
             panic!("{:?} is not a pipeline gadget state", self);
         }
     }
+    fn loopy_gadget(&self) -> &LoopyGadgetState {
+        if let Self::LoopyGadget(res) = self {
+            res
+        } else {
+            panic!("{:?} is not a loopy gadget state", self);
+        }
+    }
+    fn loopy_gadget_mut(&mut self) -> &mut LoopyGadgetState {
+        if let Self::LoopyGadget(res) = self {
+            res
+        } else {
+            panic!("{:?} is not a loopy gadget state", self);
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -237,7 +273,11 @@ pub struct ModuleState {
 pub struct GateState {
     prev_inputs: InputVec<Option<WireState>>,
     inputs: InputVec<Option<WireState>>,
-    //output: Option<WireState>,
+    /// For DFFs and latches, the stored value (for DFFs, this is the output, which is updated at
+    /// the end of the cycle).
+    stored: Option<WireState>,
+    /// For DFFs and latches, the stored value at the beginning of the cycle.
+    prev_stored: Option<WireState>,
 }
 
 #[derive(Debug, Clone)]
@@ -247,6 +287,35 @@ pub struct PipelineGadgetState {
     module_state: ModuleState,
 }
 
+#[derive(Debug, Clone)]
+pub struct LoopyGadgetState {
+    module_state: ModuleState,
+    /// Value of the 'matchi_active' signals of the gadget.
+    active: ActiveWireVec<bool>,
+    /// Number of cycles since the start of the last execution.
+    exec_cycle: Option<usize>,
+}
+
+/// A state element that stores a share-sensitive value.
+#[derive(Debug, Clone)]
+pub struct SensitiveState {
+    /// Instance path, relative to the evaluator.
+    pub path: Vec<String>,
+    /// Pipeline stage, for pipeline gadgets.
+    pub stage: Option<Latency>,
+    pub sensitivity: ShareSet,
+}
+
+impl std::fmt::Display for SensitiveState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.join("."))?;
+        if let Some(stage) = self.stage {
+            write!(f, " (pipeline stage {})", stage)?;
+        }
+        write!(f, ", shares {}", self.sensitivity)
+    }
+}
+
 #[derive(Debug, Clone)]
 struct PipelineStageStatus {
     // All inputs are deterministic.
@@ -361,6 +430,9 @@ impl Evaluator for ModuleEvaluator {
         }
         Some(module.instances[inst_id].name.clone())
     }
+    fn sensitive_state(&self, state: &EvaluatorState, netlist: &Netlist) -> Vec<SensitiveState> {
+        self.sensitive_state_inner(state.module(), netlist)
+    }
     fn debug_state(&self, state: &EvaluatorState, netlist: &Netlist) {
         let state = state.module();
         eprintln!(
@@ -389,12 +461,123 @@ impl Evaluator for ModuleEvaluator {
     }
 }
 
+impl GateEvaluator {
+    /// Stored value of a DFF or latch, as seen on its output.
+    fn stored_output(&self, stored: WireState) -> WireState {
+        if self.stop_glitches {
+            stored.stop_glitches()
+        } else {
+            WireState {
+                glitch_free: false,
+                ..stored
+            }
+        }
+    }
+    /// For a DFF, the shares that are mixed inside the cell, in front of the flip-flop, by a
+    /// non-deterministic enable (MUX with the stored value) or reset (AND/OR gate). None if the
+    /// enable and reset are deterministic.
+    fn dff_mixed_sensitivity(&self, dff: Dff, state: &GateState) -> Option<ShareSet> {
+        let input = |i: InputId| state.inputs[i].as_ref().unwrap();
+        // Enable and reset are only sampled at the clock edge: their glitches do not matter.
+        let sampled = |i: InputId| input(i).deterministic && input(i).value.is_some();
+        let d = &input(InputId::from_usize(1)).sensitivity;
+        let stored = &state.prev_stored.as_ref().unwrap().sensitivity;
+        let mut mixing = false;
+        let next = match (dff.enable, dff.enable_input()) {
+            (Some(active), Some(enable)) if sampled(enable) => {
+                if input(enable).value == Some(active) {
+                    d.clone()
+                } else {
+                    stored.clone()
+                }
+            }
+            (Some(_), Some(enable)) => {
+                mixing = true;
+                stored.union(d).union(&input(enable).sensitivity)
+            }
+            _ => d.clone(),
+        };
+        let res = match dff.reset_input() {
+            Some(reset) if !sampled(reset) => {
+                mixing = true;
+                next.union(&input(reset).sensitivity)
+            }
+            _ => next,
+        };
+        mixing.then_some(res)
+    }
+    /// For a latch, the stored value reaches the output together with the D input (i.e., the
+    /// enable is not a stable control).
+    fn ungated_stored(&self, inputs: &InputVec<Option<WireState>>) -> bool {
+        matches!(self.gate, Gate::Latch(_))
+            && !inputs[Latch::ENABLE_INPUT]
+                .as_ref()
+                .unwrap()
+                .is_stable_control()
+    }
+    /// Inputs whose glitches can reach the output (the other ones are gated by a stable control
+    /// input).
+    fn ungated_inputs(
+        &self,
+        out: OutputId,
+        inputs: &InputVec<Option<WireState>>,
+    ) -> InputVec<bool> {
+        let input = |i: usize| inputs[InputId::from_usize(i)].as_ref().unwrap();
+        let mut res = InputVec::from_vec(vec![true; inputs.len()]);
+        match self.gate {
+            Gate::CombBinary(bgate) => {
+                let absorbing = |i: usize| {
+                    input(i).is_stable_control()
+                        && bgate
+                            .input_absorb(i)
+                            .is_some_and(|v| input(i).value == Some(v))
+                };
+                if absorbing(0) || absorbing(1) {
+                    res.iter_mut().for_each(|x| *x = false);
+                }
+            }
+            Gate::Mux { .. } => {
+                let ops = input(2);
+                if ops.is_stable_control() {
+                    res[InputId::from_usize(0)] = ops.value == Some(WireValue::_0);
+                    res[InputId::from_usize(1)] = ops.value == Some(WireValue::_1);
+                }
+            }
+            Gate::Latch(latch) => {
+                let enable = &inputs[Latch::ENABLE_INPUT].as_ref().unwrap();
+                if enable.is_stable_control() {
+                    res[Latch::D_INPUT] = enable.value == Some(latch.enable);
+                }
+            }
+            Gate::Lut(_) | Gate::Adder(_) => {
+                let lut = self.gate.lut_function(out).unwrap();
+                let stable = lut.restrict(
+                    &inputs
+                        .iter()
+                        .map(|op| {
+                            let op = op.as_ref().unwrap();
+                            op.is_stable_control().then_some(op.value).flatten()
+                        })
+                        .collect::<Vec<_>>(),
+                );
+                for (i, ungated) in res.iter_mut().enumerate() {
+                    *ungated = stable.depends_on(i);
+                }
+            }
+            Gate::CombUnitary(_) | Gate::Dff(_) => {}
+        }
+        res
+    }
+}
+
 impl Evaluator for GateEvaluator {
     fn init_next(&self, prev_state: &EvaluatorState, _netlist: &Netlist) -> EvaluatorState {
         let prev_state = prev_state.gate();
         EvaluatorState::Gate(GateState {
             prev_inputs: prev_state.inputs.clone(),
             inputs: InputVec::from_vec(vec![None; self.gate.input_ports().len()]),
+            stored: prev_state.stored.clone(),
+            prev_stored: prev_state.stored.clone(),
         })
     }
     fn x_state(&self, _netlist: &Netlist) -> EvaluatorState {
@@ -407,6 +590,8 @@ impl Evaluator for GateEvaluator {
                 Some(WireState::control());
                 self.gate.input_ports().len()
             ]),
+            stored: Some(WireState::control()),
+            prev_stored: Some(WireState::control()),
         })
     }
     fn set_input(
@@ -421,9 +606,9 @@ impl Evaluator for GateEvaluator {
     }
     fn eval_output(
         &self,
-        _out: OutputId,
+        out: OutputId,
         state: &mut EvaluatorState,
-        sim_state: Option<&mut GlobSimulationState>,
+        mut sim_state: Option<&mut GlobSimulationState>,
         _netlist: &Netlist,
     ) -> WireState {
         //eprintln!("Eval gate_inst_id {:?})", self.inst_id);
@@ -446,18 +631,44 @@ impl Evaluator for GateEvaluator {
                 let op1 = state.inputs[1].as_ref().unwrap();
                 bgate.sim(op0, op1, sim_state, self.inst_id)
             }
-            Gate::Mux => {
+            Gate::Mux { inverted } => {
                 let op0 = state.inputs[0].as_ref().unwrap();
                 let op1 = state.inputs[1].as_ref().unwrap();
                 let ops = state.inputs[2].as_ref().unwrap();
-                super::simulation::sim_mux(op0, op1, ops, sim_state, self.inst_id)
+                let res = super::simulation::sim_mux(
+                    op0,
+                    op1,
+                    ops,
+                    sim_state.as_deref_mut(),
+                    self.inst_id,
+                );
+                if inverted {
+                    // Same as a NOT gate after the MUX.
+                    if let Some(sim_state) = sim_state {
+                        sim_state.leak_random(&res, self.inst_id);
+                    }
+                    res.negate()
+                } else {
+                    res
+                }
             }
-            Gate::Dff => {
-                // TODO: only stop glitches for some specifically-marked gates?
-                state.prev_inputs[1]
-                    .clone()
-                    .unwrap_or(WireState::control())
-                    .stop_glitches()
+            Gate::Lut(_) | Gate::Adder(_) => {
+                let lut = self.gate.lut_function(out).unwrap();
+                let ops = state
+                    .inputs
+                    .iter()
+                    .map(|op| op.as_ref().unwrap())
+                    .collect::<Vec<_>>();
+                lut.sim(&ops, sim_state, self.inst_id)
+            }
+            Gate::Latch(latch) => latch.sim_output(
+                &state.inputs,
+                &self.stored_output(state.stored.clone().unwrap()),
+                sim_state,
+                self.inst_id,
+            ),
+            Gate::Dff(_) => {
+                self.stored_output(state.stored.clone().unwrap_or(WireState::control()))
             }
         };
         //eprintln!("done Eval gate_inst_id {:?})", self.inst_id);
@@ -466,74 +677,161 @@ impl Evaluator for GateEvaluator {
     fn eval_finish(
         &self,
         state: &mut EvaluatorState,
-        sim_state: Option<&mut GlobSimulationState>,
+        mut sim_state: Option<&mut GlobSimulationState>,
         _netlist: &Netlist,
     ) {
         let state = state.gate_mut();
-        if self.gate == Gate::Dff {
-            let wire_state = state.inputs[1].as_ref().unwrap();
+        if let Gate::Dff(dff) = self.gate {
+            let stored = state.stored.take().unwrap_or(WireState::control());
+            let stored = dff.sim(
+                &state.inputs,
+                &stored,
+                sim_state.as_deref_mut(),
+                self.inst_id,
+            );
             if let Some(sim_state) = sim_state {
-                sim_state.store_random(wire_state);
+                sim_state.store_random(&stored);
             }
+            state.stored = Some(stored);
+        } else if let Gate::Latch(latch) = self.gate {
+            let stored = latch.sim_stored(
+                &state.inputs,
+                state.stored.as_ref().unwrap(),
+                sim_state.as_deref_mut(),
+                self.inst_id,
+            );
+            if let Some(sim_state) = sim_state {
+                sim_state.store_random(&stored);
+            }
+            state.stored = Some(stored);
         }
     }
     fn check_safe_out(
         &self,
-        _out: OutputId,
+        out: OutputId,
         state: &mut EvaluatorState,
-        _sim_state: &mut GlobSimulationState,
+        sim_state: &mut GlobSimulationState,
         _netlist: &Netlist,
     ) -> Result<()> {
         //eprintln!("check safe out gate {:?}", self);
-        if self.gate == Gate::Dff {
+        let state = state.gate();
+        if let Gate::Dff(dff) = self.gate {
             // For a Dff, the output is a one-cycle delayed version of its input.
-            // We can assume that the safety of the input was checked.
+            // We can assume that the safety of the input was checked, except for the mixing of
+            // the input with the stored value by a non-deterministic enable or reset.
+            let Some(sensitive_mixed) = self.dff_mixed_sensitivity(dff, state) else {
+                return Ok(());
+            };
+            if let Err(probes) = sim_state.probe(&sensitive_mixed) {
+                bail!(Violation::new(
+                    ViolationKind::GateMultiShare,
+                    format!(
+                        "DFF with non-deterministic enable or reset mixes its inputs and stored value, which are sensitive in multiple shares:\n\t{}\n\tStored value, shares: {}",
+                        self.gate
+                            .input_ports()
+                            .iter_enumerated()
+                            .map(|(input_id, input_name)| format!(
+                                "Input {}, shares: {}",
+                                input_name,
+                                state.inputs[input_id].as_ref().unwrap().sensitivity,
+                            ))
+                            .collect::<Vec<_>>()
+                            .join("\n\t"),
+                        state.prev_stored.as_ref().unwrap().sensitivity,
+                    )
+                )
+                .at_inst(self.inst_id)
+                .with_shares([sensitive_mixed])
+                .with_joint_probes(probes));
+            }
             return Ok(());
         }
         // We check that the output of the gate is safe by checking its input properties.
-        let state = state.gate();
-        let sensitive_current = state.inputs.iter().fold(ShareSet::empty(), |x, y| {
-            x.union(y.as_ref().unwrap().sensitivity)
-        });
-        if sensitive_current.len() > 1 {
-            bail!(
-                "Gate has input sensitive in multiple shares (causes glitch leakage):\n\t{}",
-                self.gate
-                    .input_ports()
-                    .iter_enumerated()
-                    .map(|(input_id, input_name)| format!(
-                        "Input {}, shares: {}",
-                        input_name,
-                        state.inputs[input_id].as_ref().unwrap().sensitivity,
-                    ))
-                    .collect::<Vec<_>>()
-                    .join("\n\t")
-            );
+        let ungated_sensitivity = |inputs: &InputVec<Option<WireState>>| {
+            izip!(inputs, self.ungated_inputs(out, inputs))
+                .filter(|(_, ungated)| *ungated)
+                .fold(ShareSet::empty(), |x, (y, _)| {
+                    x.union(&y.as_ref().unwrap().sensitivity)
+                })
+        };
+        // For a latch, the stored value is mixed with the D input unless the enable is stable.
+        let stored = self
+            .ungated_stored(&state.inputs)
+            .then(|| &state.prev_stored.as_ref().unwrap().sensitivity);
+        let sensitive_current =
+            ungated_sensitivity(&state.inputs).union(stored.unwrap_or(&ShareSet::empty()));
+        if let Err(probes) = sim_state.probe(&sensitive_current) {
+            bail!(Violation::new(
+                ViolationKind::GateMultiShare,
+                format!(
+                    "Gate has input sensitive in multiple shares (causes glitch leakage):\n\t{}",
+                    self.gate
+                        .input_ports()
+                        .iter_enumerated()
+                        .map(|(input_id, input_name)| format!(
+                            "Input {}, shares: {}",
+                            input_name,
+                            state.inputs[input_id].as_ref().unwrap().sensitivity,
+                        ))
+                        .chain(stored.map(|stored| format!("Stored value, shares: {}", stored)))
+                        .collect::<Vec<_>>()
+                        .join("\n\t")
+                )
+            )
+            .at_inst(self.inst_id)
+            .with_shares(
+                state
+                    .inputs
+                    .iter()
+                    .map(|input| input.as_ref().unwrap().sensitivity.clone())
+                    .chain(stored.cloned())
+            )
+            .with_joint_probes(probes));
         }
-        let sensitive_prev = state
-            .inputs
-            .iter()
-            .map(|x| x.as_ref().unwrap().sensitivity)
-            .fold(ShareSet::empty(), |x, y| x.union(y));
-        let sensitive_transition = sensitive_current.union(sensitive_prev);
-        if sensitive_transition.len() > 1 {
-            bail!(
-                "Gate has input sensitive in multiple shares over consecutive cycles (transition leakage):\n\t{}",
-                self.gate
-                    .input_ports()
-                    .iter_enumerated()
-                    .map(|(input_id, input_name)| format!(
-                        "Input {}, shares: {}, shares previous cycle: {}",
-                        input_name,
-                        state.inputs[input_id].as_ref().unwrap().sensitivity,
-                        state.prev_inputs[input_id].as_ref().unwrap().sensitivity,
-                    ))
-                    .collect::<Vec<_>>()
-                    .join("\n\t")
-            );
+        if crate::config::config().no_check_transitions {
+            return Ok(());
+        }
+        let sensitive_prev = ungated_sensitivity(&state.prev_inputs);
+        let sensitive_transition = sensitive_current.union(&sensitive_prev);
+        if let Err(probes) = sim_state.probe(&sensitive_transition) {
+            bail!(Violation::new(
+                ViolationKind::GateTransition,
+                format!(
+                    "Gate has input sensitive in multiple shares over consecutive cycles (transition leakage):\n\t{}",
+                    self.gate
+                        .input_ports()
+                        .iter_enumerated()
+                        .map(|(input_id, input_name)| format!(
+                            "Input {}, shares: {}, shares previous cycle: {}",
+                            input_name,
+                            state.inputs[input_id].as_ref().unwrap().sensitivity,
+                            state.prev_inputs[input_id].as_ref().unwrap().sensitivity,
+                        ))
+                        .collect::<Vec<_>>()
+                        .join("\n\t")
+                )
+            )
+            .at_inst(self.inst_id)
+            .with_shares([sensitive_current, sensitive_prev])
+            .with_joint_probes(probes));
         }
         Ok(())
     }
+    fn sensitive_state(&self, state: &EvaluatorState, _netlist: &Netlist) -> Vec<SensitiveState> {
+        if !matches!(self.gate, Gate::Dff(_) | Gate::Latch(_)) {
+            return vec![];
+        }
+        let sensitivity = state.gate().stored.as_ref().unwrap().sensitivity.clone();
+        if sensitivity.is_empty() {
+            vec![]
+        } else {
+            vec![SensitiveState {
+                path: vec![],
+                stage: None,
+                sensitivity,
+            }]
+        }
+    }
     fn debug_state(&self, state: &EvaluatorState, netlist: &Netlist) {
         eprintln!(
             "gate {:?}, inst_id: {:?}, state: {:?}",
@@ -567,7 +865,9 @@ impl Evaluator for TieEvaluator {
         _sim_state: Option<&mut GlobSimulationState>,
         _netlist: &Netlist,
     ) -> WireState {
-        WireState::control().with_value(Some(self.value))
+        WireState::control()
+            .with_value(Some(self.value))
+            .stop_glitches()
     }
     fn debug_state(&self, state: &EvaluatorState, netlist: &Netlist) {
         eprintln!("Tie {:?}", self.value);
@@ -618,13 +918,12 @@ impl Evaluator for PipelineGadgetEvaluator {
         );
         */
         let state = state.pipeline_gadget_mut();
-        state.inputs[Latency::from_raw(0)][input] = Some(input_state.clone());
         self.module_evaluator
             .set_input_inner(&mut state.module_state, input, input_state, netlist);
-        assert_eq!(
-            state.module_state.wire_states[module.connection_wires[module.input_ports[input]]],
-            state.inputs[0][input]
-        );
+        // Take the wire state from the module, as it includes the wire annotations.
+        state.inputs[Latency::from_raw(0)][input] = state.module_state.wire_states
+            [module.connection_wires[module.input_ports[input]]]
+            .clone();
     }
     fn check_safe_input(
         &self,
@@ -637,54 +936,68 @@ impl Evaluator for PipelineGadgetEvaluator {
         let module = netlist.module(self.module_id);
         let gadget = netlist.gadget(self.module_id).unwrap();
         let wire_state = state.inputs[0][input].as_ref().unwrap();
+        let port_name = &module.ports[module.input_ports[input]];
+        let ginst_id = self.module_evaluator.ginst_id;
         match &gadget.input_roles[input] {
             PortRole::Share(share_id) => {
-                if !wire_state.sensitivity.subset_of(ShareSet::from(*share_id)) {
-                    Err(anyhow!(
-                        "Input share index {} is sensitive for shares {}",
-                        share_id,
-                        wire_state.sensitivity
-                    ))
-                } else if !wire_state.sensitivity.subset_of(ShareSet::from(*share_id)) {
-                    Err(anyhow!(
-                        "Input share index {} is glitch-sensitive for shares {}",
-                        share_id,
-                        wire_state.glitch_sensitivity
-                    ))
+                if !wire_state.sensitivity.subset_of(&ShareSet::from(*share_id)) {
+                    Err(Violation::new(
+                        ViolationKind::GadgetInputShare,
+                        format!(
+                            "Input share index {} is sensitive for shares {}",
+                            share_id, wire_state.sensitivity
+                        ),
+                    )
+                    .with_shares([wire_state.sensitivity.clone()]))
+                } else if !wire_state
+                    .glitch_sensitivity
+                    .subset_of(&ShareSet::from(*share_id))
+                {
+                    Err(Violation::new(
+                        ViolationKind::GadgetInputShare,
+                        format!(
+                            "Input share index {} is glitch-sensitive for shares {}",
+                            share_id, wire_state.glitch_sensitivity
+                        ),
+                    )
+                    .with_shares([wire_state.glitch_sensitivity.clone()]))
                 } else {
                     Ok(())
                 }
             }
             PortRole::Random(_) => {
                 if !wire_state.glitch_sensitivity.is_empty() {
-                    Err(anyhow!(
-                        "Randomness input is (glitch-)sensitive for shares {}",
-                        wire_state.glitch_sensitivity
-                    ))
+                    Err(Violation::new(
+                        ViolationKind::GadgetInputRandom,
+                        format!(
+                            "Randomness input is (glitch-)sensitive for shares {}",
+                            wire_state.glitch_sensitivity
+                        ),
+                    )
+                    .with_shares([wire_state.glitch_sensitivity.clone()]))
                 } else {
                     Ok(())
                 }
             }
             PortRole::Control => {
                 if !wire_state.deterministic {
-                    Err(anyhow!(
+                    Err(Violation::new(
+                        ViolationKind::ControlNotDeterministic,
                         "Control input is not a deterministic value (it is share- or random-dependent)",
                     ))
                 } else if !wire_state.glitch_sensitivity.is_empty() {
-                    Err(anyhow!(
-                        "Control input depends of share glitches."
-                    ))
+                    Err(Violation::new(
+                        ViolationKind::ControlNotDeterministic,
+                        "Control input depends of share glitches.",
+                    )
+                    .with_shares([wire_state.glitch_sensitivity.clone()]))
                 } else {
                     Ok(())
                 }
             }
         }
-        .with_context(|| {
-            format!(
-                "Unsafe state for input {}",
-                module.ports[module.input_ports[input]],
-            )
-        })?;
+        .map_err(|violation| violation.at_inst(ginst_id).on_wire(port_name))
+        .with_context(|| format!("Unsafe state for input {}", port_name))?;
         if !crate::config::config().no_check_transitions
             && gadget.prop.requires_bubble()
             && wire_state.nspgi_dep.last(self.nspgi_id).is_some_and(|dep| {
@@ -693,12 +1006,16 @@ impl Evaluator for PipelineGadgetEvaluator {
                     .unwrap_or(true)
             })
         {
-            bail!(
-                "Transition leakage failure: Input {} of gadget {} depends on a previous \
-                execution of this gadget, there was no pipeline bubble since then.",
-                module.ports[module.input_ports[input]],
-                module.name
-            );
+            bail!(Violation::new(
+                ViolationKind::GadgetTransition,
+                format!(
+                    "Transition leakage failure: Input {} of gadget {} depends on a previous \
+                    execution of this gadget, there was no pipeline bubble since then.",
+                    port_name, module.name
+                )
+            )
+            .at_inst(ginst_id)
+            .on_wire(port_name));
         }
         Ok(())
     }
@@ -720,7 +1037,13 @@ impl Evaluator for PipelineGadgetEvaluator {
                 let lat = gadget.input_maxrellat(*input_id, netlist);
                 let random_wire_state = state.inputs[lat][*input_id].as_ref().unwrap();
                 if random_wire_state.random.is_none() {
-                    bail!("Gadget execution has at least one sensitive input but randomness wire {} is not a fresh random", module.ports[module.input_ports[*input_id]]);
+                    let port_name = &module.ports[module.input_ports[*input_id]];
+                    bail!(Violation::new(
+                        ViolationKind::RandomnessNotFresh,
+                        format!("Gadget execution has at least one sensitive input but randomness wire {} is not a fresh random", port_name)
+                    )
+                    .at_inst(self.module_evaluator.ginst_id)
+                    .on_wire(port_name));
                 }
             }
         }
@@ -751,8 +1074,13 @@ impl Evaluator for PipelineGadgetEvaluator {
         let res =
             self.module_evaluator
                 .eval_output_inner(out, &mut state.module_state, None, netlist);
+        let Some(sim_state) = sim_state else {
+            // This gadget is inside a gadget that is itself simulated as a black box, only the
+            // value of the output matters.
+            return res;
+        };
         // Let us evaluate the output based solely on the gadget annotations.
-        let out_status = self.out_status(state, out_lat, sim_state.unwrap(), netlist);
+        let out_status = self.out_status(state, out_lat, sim_state, netlist);
         let share_id = gadget.output_share_id[out];
         let g_res = WireState {
             sensitivity: ShareSet::from(share_id).clear_if(!out_status.sensitive),
@@ -761,6 +1089,7 @@ impl Evaluator for PipelineGadgetEvaluator {
             random: None,
             deterministic: out_status.deterministic,
             nspgi_dep: out_status.nspgi_dep.clone(),
+            glitch_free: false,
         };
         if out_status.sensitive {
             assert!(out_status.glitch_sensitive);
@@ -793,9 +1122,11 @@ impl Evaluator for PipelineGadgetEvaluator {
         netlist: &Netlist,
     ) {
         let state = state.pipeline_gadget_mut();
-        let sim_state = sim_state.unwrap();
         self.module_evaluator
             .eval_finish_inner(&mut state.module_state, None, netlist);
+        let Some(sim_state) = sim_state else {
+            return;
+        };
         // use the fresh randomness
         // We look back max_input_latency cycles, so we have all required inputs available.
         let module = netlist.module(self.module_id);
@@ -848,6 +1179,37 @@ impl Evaluator for PipelineGadgetEvaluator {
     fn glob_inst2path(&self, ginst: GlobInstId, netlist: &Netlist) -> Option<String> {
         self.module_evaluator.glob_inst2path(ginst, netlist)
     }
+    fn sensitive_state(&self, state: &EvaluatorState, netlist: &Netlist) -> Vec<SensitiveState> {
+        let state = state.pipeline_gadget();
+        let gadget = netlist.gadget(self.module_id).unwrap();
+        let module = netlist.module(self.module_id);
+        // After the clock edge, the stage lat+1 holds data computed from the inputs of the
+        // execution that started lat cycles ago.
+        (0..gadget.max_latency.index())
+            .map(Latency::from_usize)
+            .filter_map(|lat| {
+                let sensitivity = module
+                    .input_ports
+                    .iter_enumerated()
+                    .filter_map(|(input_id, con_id)| {
+                        let lat_diff = lat.checked_sub(gadget.latency[*con_id])?;
+                        Some(
+                            state.inputs[lat_diff][input_id]
+                                .as_ref()
+                                .expect("uninitialized input")
+                                .sensitivity
+                                .clone(),
+                        )
+                    })
+                    .fold(ShareSet::empty(), |x, y| x.union(&y));
+                (!sensitivity.is_empty()).then(|| SensitiveState {
+                    path: vec![],
+                    stage: Some(lat + 1usize),
+                    sensitivity,
+                })
+            })
+            .collect()
+    }
     fn debug_state(&self, state: &EvaluatorState, netlist: &Netlist) {
         let state = state.pipeline_gadget();
         eprintln!(
@@ -860,6 +1222,222 @@ impl Evaluator for PipelineGadgetEvaluator {
     }
 }
 
+impl Evaluator for LoopyGadgetEvaluator {
+    fn init_next(&self, prev_state: &EvaluatorState, netlist: &Netlist) -> EvaluatorState {
+        let prev_state = prev_state.loopy_gadget();
+        EvaluatorState::LoopyGadget(LoopyGadgetState {
+            module_state: self
+                .module_evaluator
+                .init_next_inner(&prev_state.module_state, netlist),
+            // Updated in eval_finish, once the signals are evaluated.
+            active: prev_state.active.clone(),
+            exec_cycle: prev_state.exec_cycle.map(|cycle| cycle + 1),
+        })
+    }
+    fn x_state(&self, netlist: &Netlist) -> EvaluatorState {
+        let gadget = &netlist.loopy_gadget(self.module_id).unwrap().gadget;
+        EvaluatorState::LoopyGadget(LoopyGadgetState {
+            module_state: self.module_evaluator.x_state_inner(netlist),
+            active: ActiveWireVec::from_vec(vec![false; gadget.active_wires.len()]),
+            exec_cycle: None,
+        })
+    }
+    fn set_input(
+        &self,
+        state: &mut EvaluatorState,
+        input: InputId,
+        input_state: WireState,
+        netlist: &Netlist,
+    ) {
+        let state = state.loopy_gadget_mut();
+        self.module_evaluator
+            .set_input_inner(&mut state.module_state, input, input_state, netlist);
+    }
+    fn eval_output(
+        &self,
+        out: OutputId,
+        state: &mut EvaluatorState,
+        _sim_state: Option<&mut GlobSimulationState>,
+        netlist: &Netlist,
+    ) -> WireState {
+        // The security of the gadget is verified on its own: here we only simulate it, without
+        // forwarding the sim_state (the randomness is handled at the border of the gadget).
+        let state = state.loopy_gadget_mut();
+        self.module_evaluator
+            .eval_output_inner(out, &mut state.module_state, None, netlist)
+    }
+    fn eval_finish(
+        &self,
+        state: &mut EvaluatorState,
+        sim_state: Option<&mut GlobSimulationState>,
+        netlist: &Netlist,
+    ) {
+        let state = state.loopy_gadget_mut();
+        self.module_evaluator
+            .eval_finish_inner(&mut state.module_state, None, netlist);
+        let loopy_gadget = netlist.loopy_gadget(self.module_id).unwrap();
+        let active = loopy_gadget
+            .active_wires
+            .iter()
+            .map(|wire| {
+                state.module_state.wire_states[*wire]
+                    .as_ref()
+                    .and_then(|wire_state| wire_state.value)
+                    == Some(WireValue::_1)
+            })
+            .collect::<ActiveWireVec<_>>();
+        if let Some(ea) = loopy_gadget.gadget.exec_active {
+            if active[ea] && !state.active[ea] {
+                state.exec_cycle = Some(0);
+            }
+        }
+        state.active = active;
+        let Some(sim_state) = sim_state else {
+            return;
+        };
+        let module = netlist.module(self.module_id);
+        let ginst_id = self.module_evaluator.ginst_id;
+        for input_id in &loopy_gadget.gadget.rnd_ports {
+            let con_id = module.input_ports[*input_id];
+            let random_wire_state = state.module_state.wire_states[module.connection_wires[con_id]]
+                .as_ref()
+                .unwrap();
+            if self.con_valid(con_id, state, netlist) {
+                sim_state.use_random(random_wire_state, ginst_id, Latency::from_raw(0));
+            }
+            sim_state.leak_random(random_wire_state, ginst_id);
+        }
+    }
+    fn check_safe_input(
+        &self,
+        state: &mut EvaluatorState,
+        _sim_state: &mut GlobSimulationState,
+        input: InputId,
+        netlist: &Netlist,
+    ) -> Result<()> {
+        let state = state.loopy_gadget();
+        let module = netlist.module(self.module_id);
+        let gadget = &netlist.loopy_gadget(self.module_id).unwrap().gadget;
+        let con_id = module.input_ports[input];
+        let wire_state = state.module_state.wire_states[module.connection_wires[con_id]]
+            .as_ref()
+            .unwrap();
+        let port_name = &module.ports[con_id];
+        let valid = self.con_valid(con_id, state, netlist);
+        // When it is not valid, a share or random input is considered as a control in the
+        // verification of the gadget.
+        match (&gadget.port_roles[con_id], valid) {
+            (PortRole::Share(share_id), true) => {
+                if !wire_state.sensitivity.subset_of(&ShareSet::from(*share_id)) {
+                    Err(Violation::new(
+                        ViolationKind::GadgetInputShare,
+                        format!(
+                            "Input share index {} is sensitive for shares {}",
+                            share_id, wire_state.sensitivity
+                        ),
+                    )
+                    .with_shares([wire_state.sensitivity.clone()]))
+                } else if !wire_state
+                    .glitch_sensitivity
+                    .subset_of(&ShareSet::from(*share_id))
+                {
+                    Err(Violation::new(
+                        ViolationKind::GadgetInputShare,
+                        format!(
+                            "Input share index {} is glitch-sensitive for shares {}",
+                            share_id, wire_state.glitch_sensitivity
+                        ),
+                    )
+                    .with_shares([wire_state.glitch_sensitivity.clone()]))
+                } else {
+                    Ok(())
+                }
+            }
+            (PortRole::Random(_), true) => {
+                if !wire_state.glitch_sensitivity.is_empty() {
+                    Err(Violation::new(
+                        ViolationKind::GadgetInputRandom,
+                        format!(
+                            "Randomness input is (glitch-)sensitive for shares {}",
+                            wire_state.glitch_sensitivity
+                        ),
+                    )
+                    .with_shares([wire_state.glitch_sensitivity.clone()]))
+                } else if wire_state.random.is_none() {
+                    Err(Violation::new(
+                        ViolationKind::RandomnessNotFresh,
+                        "Randomness input is valid, but it is not a fresh random",
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+            (PortRole::Share(_), false) | (PortRole::Random(_), false) => {
+                if !wire_state.glitch_sensitivity.is_empty() {
+                    Err(Violation::new(
+                        if matches!(gadget.port_roles[con_id], PortRole::Share(_)) {
+                            ViolationKind::GadgetInputShare
+                        } else {
+                            ViolationKind::GadgetInputRandom
+                        },
+                        format!(
+                            "Input is not valid, but it is (glitch-)sensitive for shares {}",
+                            wire_state.glitch_sensitivity
+                        ),
+                    )
+                    .with_shares([wire_state.glitch_sensitivity.clone()]))
+                } else if !wire_state.deterministic {
+                    Err(Violation::new(
+                        ViolationKind::ControlNotDeterministic,
+                        "Input is not valid, but it is not a deterministic value",
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+            (PortRole::Control, _) => {
+                if !wire_state.deterministic {
+                    Err(Violation::new(
+                        ViolationKind::ControlNotDeterministic,
+                        "Control input is not a deterministic value (it is share- or random-dependent)",
+                    ))
+                } else if !wire_state.glitch_sensitivity.is_empty() {
+                    Err(Violation::new(
+                        ViolationKind::ControlNotDeterministic,
+                        "Control input depends of share glitches.",
+                    )
+                    .with_shares([wire_state.glitch_sensitivity.clone()]))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+        .map_err(|violation| {
+            violation
+                .at_inst(self.module_evaluator.ginst_id)
+                .on_wire(port_name)
+        })
+        .with_context(|| format!("Unsafe state for input {}", port_name))
+    }
+    fn glob_inst2path(&self, ginst: GlobInstId, netlist: &Netlist) -> Option<String> {
+        self.module_evaluator.glob_inst2path(ginst, netlist)
+    }
+    fn sensitive_state(&self, state: &EvaluatorState, netlist: &Netlist) -> Vec<SensitiveState> {
+        self.module_evaluator
+            .sensitive_state_inner(&state.loopy_gadget().module_state, netlist)
+    }
+    fn debug_state(&self, state: &EvaluatorState, netlist: &Netlist) {
+        let state = state.loopy_gadget();
+        eprintln!(
+            "Loopy gadget, module {:?}, active: {:?}, exec_cycle: {:?}",
+            self.module_id, state.active, state.exec_cycle
+        );
+        eprintln!("module state:");
+        self.module_evaluator
+            .debug_state(&EvaluatorState::Module(state.module_state.clone()), netlist);
+    }
+}
+
 impl ModuleEvaluator {
     pub fn new(
         module_id: ModuleId,
@@ -928,13 +1506,8 @@ impl ModuleEvaluator {
                 */
                 let mut path = instance_path.clone();
                 path.push(instance.name.clone());
-                let res = InstanceEvaluator::new(
-                    &instance.architecture,
-                    queries,
-                    netlist,
-                    &mut inst_id_range,
-                    path,
-                );
+                let res =
+                    InstanceEvaluator::new(instance, queries, netlist, &mut inst_id_range, path);
                 used_ids.copy_end(&inst_id_range);
                 /*
                 eprintln!(
@@ -957,6 +1530,68 @@ impl ModuleEvaluator {
             ginst_id,
         }
     }
+    /// Wires to evaluate to get the value of `wires` in a cycle, in evaluation order.
+    ///
+    /// This is the combinational fan-in cone of `wires`, extended to all the connections of the
+    /// sub-module instances in the cone, since the outputs of a sub-module are evaluated in a fixed
+    /// order.
+    pub fn value_cone(&self, wires: &[WireId], netlist: &Netlist) -> Vec<WireId> {
+        assert!(self.eval_order.is_empty());
+        let module = netlist.module(self.module_id);
+        let wg = &netlist.module_comb_deps(self.module_id).comb_wire_dag;
+        let mut in_cone = WireVec::from_vec(vec![false; module.wires.len()]);
+        let mut in_cone_instances = InstanceVec::from_vec(vec![false; module.instances.len()]);
+        let mut to_visit = wires.to_vec();
+        while let Some(wire) = to_visit.pop() {
+            if std::mem::replace(&mut in_cone[wire], true) {
+                continue;
+            }
+            to_visit.extend(
+                wg.graph
+                    .neighbors_directed(wg.node_indices[wire], petgraph::Direction::Incoming)
+                    .map(|node| wg.graph[node]),
+            );
+            let instance_id = module.wires[wire].source.0;
+            let instance = &module.instances[instance_id];
+            if matches!(instance.architecture, InstanceType::Module(_))
+                && !std::mem::replace(&mut in_cone_instances[instance_id], true)
+            {
+                to_visit.extend(instance.connections.iter().copied());
+            }
+        }
+        self.eval_finish
+            .iter()
+            .copied()
+            .filter(|wire| in_cone[*wire])
+            .collect()
+    }
+    /// Evaluate the values of the wires of a cone (see value_cone), without checking security.
+    /// The evaluation of the cycle is not finished: the state cannot be used for the next cycle.
+    pub fn eval_cone(&self, state: &mut EvaluatorState, cone: &[WireId], netlist: &Netlist) {
+        self.eval_wires(cone, state.module_mut(), None, netlist);
+    }
+    fn sensitive_state_inner(&self, state: &ModuleState, netlist: &Netlist) -> Vec<SensitiveState> {
+        let module = netlist.module(self.module_id);
+        let mut res = vec![];
+        for (inst_id, (i_eval, i_state)) in self
+            .instance_evaluators
+            .iter()
+            .zip(state.instance_states.iter())
+            .enumerate()
+        {
+            if let (Some(i_eval), Some(i_state)) = (i_eval, i_state) {
+                res.extend(i_eval.sensitive_state(i_state, netlist).into_iter().map(
+                    |mut sensitive_state| {
+                        sensitive_state
+                            .path
+                            .insert(0, module.instances[inst_id].name.clone());
+                        sensitive_state
+                    },
+                ));
+            }
+        }
+        res
+    }
     fn eval_wires(
         &self,
         wires: &[WireId],
@@ -994,11 +1629,20 @@ impl ModuleEvaluator {
                     sim_state,
                     netlist,
                 );
-            state.wire_states[wire] = Some(res);
+            state.wire_states[wire] = Some(self.annotate_wire(wire, res, netlist));
         }
         self.eval_fanout(wire, state, netlist);
         //eprintln!("eval wire done");
     }
+    /// Apply the wire annotations to its state.
+    fn annotate_wire(&self, wire: WireId, wire_state: WireState, netlist: &Netlist) -> WireState {
+        let module = netlist.module(self.module_id);
+        if module.glitch_free[wire] == Some(GlitchFree::Assume) {
+            wire_state.stop_glitches()
+        } else {
+            wire_state
+        }
+    }
     fn eval_fanout(&self, wire: WireId, state: &mut ModuleState, netlist: &Netlist) {
         //eprintln!("eval fanout of wire {}", wire);
         for (instance_id, input_id) in &netlist.module(self.module_id).wires[wire].sinks {
@@ -1031,7 +1675,7 @@ impl ModuleEvaluator {
         let module = netlist.module(self.module_id);
         for (instance_id, input_id) in &module.wires[wire].sinks {
             if let Some(sub_evaluator) = &self.instance_evaluators[*instance_id] {
-                sub_evaluator
+                let res = sub_evaluator
                     .check_safe_input(
                         state.instance_states[*instance_id].as_mut().unwrap(),
                         sim_state,
@@ -1043,7 +1687,8 @@ impl ModuleEvaluator {
                             "while checking instance {}",
                             module.instances[*instance_id].name
                         )
-                    })?;
+                    });
+                sim_state.report(res)?;
             }
         }
         Ok(())
@@ -1103,7 +1748,7 @@ impl ModuleEvaluator {
             */
         }
         assert!(state.wire_states[wire_id].is_none());
-        state.wire_states[wire_id] = Some(input_state);
+        state.wire_states[wire_id] = Some(self.annotate_wire(wire_id, input_state, netlist));
         self.eval_fanout(wire_id, state, netlist);
     }
     fn eval_output_inner(
@@ -1186,14 +1831,15 @@ impl ModuleEvaluator {
             &mut state.instance_states,
         ) {
             if let (Some(evaluator), Some(state)) = (evaluator, state.as_mut()) {
-                evaluator
+                let res = evaluator
                     .check_safe_finish(state, sim_state, netlist)
                     .with_context(|| {
                         format!(
                             "In module {}, checking instance {}.",
                             module.name, module.instances[instance_id].name
                         )
-                    })?;
+                    });
+                sim_state.report(res)?;
             } else {
                 assert!(evaluator.is_none() && state.is_none());
             }
@@ -1210,7 +1856,7 @@ impl ModuleEvaluator {
         let module = netlist.module(self.module_id);
         let (src_inst_id, src_con) = module.wires[wire].source;
         if let Some(evaluator) = self.instance_evaluators[src_inst_id].as_ref() {
-            evaluator
+            let res = evaluator
                 .check_safe_out(
                     src_con,
                     state.instance_states[src_inst_id].as_mut().unwrap(),
@@ -1222,18 +1868,37 @@ impl ModuleEvaluator {
                         "In module {}, checking instance {}",
                         module.name, module.instances[src_inst_id].name
                     )
-                })?;
+                });
+            sim_state.report(res)?;
         }
-        state.wire_states[wire]
-            .as_ref()
-            .unwrap()
-            .check_secure()
+        let wire_state = state.wire_states[wire].as_ref().unwrap();
+        if module.glitch_free[wire] == Some(GlitchFree::Assert) && !wire_state.glitch_free {
+            sim_state.report(Err(Violation::new(
+                ViolationKind::GlitchFree,
+                format!(
+                    "Wire {} in module {} is annotated as glitch-free, but it may glitch.",
+                    module.wire_name(wire),
+                    module.name
+                ),
+            )
+            .at_inst(self.ginst_id)
+            .on_wire(module.wire_name(wire))
+            .into()))?;
+        }
+        let res = wire_state
+            .check_secure(sim_state)
+            .map_err(|violation| {
+                violation
+                    .at_inst(self.ginst_id)
+                    .on_wire(module.wire_name(wire))
+            })
             .with_context(|| {
                 format!(
                     "Checking wire {:?} in module {}",
                     module.wire_names[wire], module.name
                 )
-            })?;
+            });
+        sim_state.report(res)?;
         self.check_fanout(wire, state, sim_state, netlist)?;
         Ok(())
     }
@@ -1337,15 +2002,24 @@ impl PipelineGadgetEvaluator {
     }
 }
 
+impl LoopyGadgetEvaluator {
+    /// The input or output is valid in the current cycle.
+    fn con_valid(&self, con_id: ConnectionId, state: &LoopyGadgetState, netlist: &Netlist) -> bool {
+        netlist.loopy_gadget(self.module_id).unwrap().gadget.latency[con_id]
+            .as_ref()
+            .is_some_and(|cond| cond.is_valid(state.exec_cycle, &state.active))
+    }
+}
+
 impl InstanceEvaluator {
     fn new(
-        architecture: &InstanceType,
+        instance: &Instance,
         queries: Vec<OutputId>,
         netlist: &Netlist,
         used_ids: &mut EvalInstanceIds,
         instance_path: Vec<String>,
     ) -> Option<Self> {
-        match architecture {
+        match &instance.architecture {
             InstanceType::Gate(gate) => {
                 let inst_id = used_ids.new_inst();
                 eprintln!(
@@ -1355,24 +2029,38 @@ impl InstanceEvaluator {
                 Some(InstanceEvaluator::Gate(GateEvaluator {
                     gate: *gate,
                     inst_id,
+                    stop_glitches: instance
+                        .stop_glitches
+                        .unwrap_or(!crate::config::config().no_dff_stop_glitches),
                 }))
             }
-            InstanceType::Module(submodule_id) => match netlist.gadget(*submodule_id) {
-                Some(_gadget) => Some(InstanceEvaluator::Gadget(PipelineGadgetEvaluator::new(
-                    *submodule_id,
-                    netlist,
-                    queries,
-                    used_ids,
-                    instance_path,
-                ))),
-                None => Some(InstanceEvaluator::Module(ModuleEvaluator::new(
-                    *submodule_id,
-                    netlist,
-                    queries,
-                    used_ids,
-                    instance_path,
-                ))),
-            },
+            InstanceType::Module(submodule_id) => {
+                if netlist.gadget(*submodule_id).is_some() {
+                    Some(InstanceEvaluator::Gadget(PipelineGadgetEvaluator::new(
+                        *submodule_id,
+                        netlist,
+                        queries,
+                        used_ids,
+                        instance_path,
+                    )))
+                } else {
+                    let module_evaluator = ModuleEvaluator::new(
+                        *submodule_id,
+                        netlist,
+                        queries,
+                        used_ids,
+                        instance_path,
+                    );
+                    Some(if netlist.loopy_gadget(*submodule_id).is_some() {
+                        InstanceEvaluator::LoopyGadget(LoopyGadgetEvaluator {
+                            module_id: *submodule_id,
+                            module_evaluator,
+                        })
+                    } else {
+                        InstanceEvaluator::Module(module_evaluator)
+                    })
+                }
+            }
             // TODO: Create an evaluator for this, in order to check security.
             InstanceType::Input(..) => None,
             InstanceType::Tie(value) => {
@@ -1,4 +1,6 @@
-use itertools::Itertools;
+use bit_set::BitSet;
+use itertools::{Either, Itertools};
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct ShareId(u32);
@@ -7,53 +9,106 @@ impl ShareId {
     pub fn from_raw(x: u32) -> Self {
         ShareId(x)
     }
+    pub fn raw(self) -> u32 {
+        self.0
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct ShareSet(u64);
+/// Set of shares, stored inline for the shares 0 to 63, and as a heap bitset for sets that
+/// contain larger shares (such that the representation of a set is unique).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShareSet {
+    Inline(u64),
+    Heap(Arc<BitSet>),
+}
 
 impl ShareSet {
+    const INLINE_BITS: u32 = u64::BITS;
     pub fn empty() -> Self {
-        Self(0)
+        Self::Inline(0)
     }
     pub fn from(x: ShareId) -> Self {
-        assert!(x.0 < 64);
-        Self(1 << x.0)
+        if x.0 < Self::INLINE_BITS {
+            Self::Inline(1 << x.0)
+        } else {
+            let mut set = BitSet::with_capacity(x.0 as usize + 1);
+            set.insert(x.0 as usize);
+            Self::Heap(Arc::new(set))
+        }
+    }
+    fn to_bit_set(&self) -> BitSet {
+        match self {
+            Self::Inline(x) => (0..Self::INLINE_BITS)
+                .filter(|i| (x >> i) & 1 == 1)
+                .map(|i| i as usize)
+                .collect(),
+            Self::Heap(set) => BitSet::clone(set),
+        }
+    }
+    fn from_bit_set(set: BitSet) -> Self {
+        if set.iter().all(|i| i < Self::INLINE_BITS as usize) {
+            Self::Inline(set.iter().fold(0, |x, i| x | (1 << i)))
+        } else {
+            Self::Heap(Arc::new(set))
+        }
     }
-    pub fn union(self, rhs: Self) -> Self {
-        Self(self.0 | rhs.0)
+    /// Combine two sets with op, computed inline or with bitsets.
+    fn combine(
+        &self,
+        rhs: &Self,
+        inline_op: impl Fn(u64, u64) -> u64,
+        heap_op: impl Fn(&mut BitSet, &BitSet),
+    ) -> Self {
+        match (self, rhs) {
+            (Self::Inline(x), Self::Inline(y)) => Self::Inline(inline_op(*x, *y)),
+            _ => {
+                let mut res = self.to_bit_set();
+                heap_op(&mut res, &rhs.to_bit_set());
+                Self::from_bit_set(res)
+            }
+        }
     }
-    pub fn intersection(self, rhs: Self) -> Self {
-        Self(self.0 & rhs.0)
+    pub fn union(&self, rhs: &Self) -> Self {
+        self.combine(rhs, |x, y| x | y, BitSet::union_with)
     }
-    pub fn difference(self, rhs: Self) -> Self {
-        Self(self.0 & !rhs.0)
+    pub fn difference(&self, rhs: &Self) -> Self {
+        self.combine(rhs, |x, y| x & !y, BitSet::difference_with)
     }
-    pub fn subset_of(self, rhs: Self) -> bool {
+    pub fn subset_of(&self, rhs: &Self) -> bool {
         self.difference(rhs).is_empty()
     }
     pub fn is_empty(&self) -> bool {
-        self.0 == 0
+        // Heap sets are never empty.
+        matches!(self, Self::Inline(0))
     }
     pub fn contains(&self, x: ShareId) -> bool {
-        !self.intersection(Self::from(x)).is_empty()
+        match self {
+            Self::Inline(set) => x.0 < Self::INLINE_BITS && (set >> x.0) & 1 == 1,
+            Self::Heap(set) => set.contains(x.0 as usize),
+        }
     }
     pub fn len(&self) -> usize {
-        self.0.count_ones() as usize
-    }
-    pub fn iter(&self) -> impl Iterator<Item = ShareId> {
-        let mut x = self.0;
-        let mut tot_shift = 0;
-        std::iter::from_fn(move || {
-            if x == 0 {
-                None
-            } else {
-                let shift = x.trailing_zeros() + 1;
-                tot_shift += shift;
-                x >>= shift;
-                Some(ShareId(tot_shift - 1))
+        match self {
+            Self::Inline(set) => set.count_ones() as usize,
+            Self::Heap(set) => set.len(),
+        }
+    }
+    pub fn iter(&self) -> impl Iterator<Item = ShareId> + '_ {
+        match self {
+            Self::Inline(set) => {
+                let mut x = *set;
+                Either::Left(std::iter::from_fn(move || {
+                    if x == 0 {
+                        None
+                    } else {
+                        let share = x.trailing_zeros();
+                        x &= x - 1;
+                        Some(ShareId(share))
+                    }
+                }))
             }
-        })
+            Self::Heap(set) => Either::Right(set.iter().map(|i| ShareId(i as u32))),
+        }
     }
     pub fn clear_if(self, cond: bool) -> Self {
         if cond {
@@ -64,6 +119,12 @@ impl ShareSet {
     }
 }
 
+impl FromIterator<ShareId> for ShareSet {
+    fn from_iter<I: IntoIterator<Item = ShareId>>(iter: I) -> Self {
+        Self::from_bit_set(iter.into_iter().map(|x| x.0 as usize).collect())
+    }
+}
+
 impl std::fmt::Display for ShareId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -74,3 +135,58 @@ impl std::fmt::Display for ShareSet {
         write!(f, "ShareSet{{{}}}", self.iter().join(", "))
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn set(shares: &[u32]) -> ShareSet {
+        shares.iter().copied().map(ShareId).collect()
+    }
+
+    #[test]
+    fn inline_heap_boundary() {
+        assert!(matches!(set(&[63]), ShareSet::Inline(_)));
+        assert!(matches!(set(&[64]), ShareSet::Heap(_)));
+        assert!(matches!(set(&[0, 63]), ShareSet::Inline(_)));
+        assert!(matches!(set(&[0, 64]), ShareSet::Heap(_)));
+        let s = set(&[1, 63, 64, 100]);
+        assert_eq!(s.len(), 4);
+        assert!(s.contains(ShareId(63)) && s.contains(ShareId(64)) && s.contains(ShareId(100)));
+        assert!(!s.contains(ShareId(65)));
+        assert!(!set(&[63]).contains(ShareId(64)));
+        assert_eq!(
+            s.iter().collect::<Vec<_>>(),
+            [1, 63, 64, 100].map(ShareId).to_vec()
+        );
+        assert_eq!(s.to_string(), "ShareSet{1, 63, 64, 100}");
+    }
+
+    #[test]
+    fn canonical_after_shrink() {
+        let s = set(&[3, 64]);
+        let shrunk = s.difference(&set(&[64]));
+        assert!(matches!(shrunk, ShareSet::Inline(_)));
+        assert_eq!(shrunk, set(&[3]));
+        let emptied = s.difference(&s);
+        assert!(emptied.is_empty());
+        assert_eq!(emptied, ShareSet::empty());
+        // Difference with a heap set, from an inline set.
+        assert_eq!(set(&[3, 5]).difference(&set(&[5, 70])), set(&[3]));
+        assert!(set(&[3]).subset_of(&set(&[3, 70])));
+        assert!(!set(&[3, 70]).subset_of(&set(&[3])));
+        assert!(set(&[70]).subset_of(&set(&[3, 70])));
+    }
+
+    #[test]
+    fn equality() {
+        // Built in different orders and with different bitset capacities.
+        assert_eq!(set(&[1, 200, 65]), set(&[65, 1]).union(&set(&[200])));
+        assert_eq!(set(&[65]), set(&[65, 200]).difference(&set(&[200])));
+        assert_ne!(set(&[1, 65]), set(&[1]));
+        assert_ne!(set(&[63]), set(&[64]));
+        assert_eq!(set(&[0, 1]).union(&set(&[1, 2])), set(&[0, 1, 2]));
+        assert_eq!(set(&[70, 2, 70, 2]), set(&[2, 70]));
+        assert_eq!(set(&[]), ShareSet::empty());
+    }
+}
@@ -1,10 +1,11 @@
 use super::gadget::RndPortId;
-use super::module::gates::CombBinary;
+use super::module::gates::{CombBinary, Dff, Latch, Lut};
+use super::module::{InputId, InputVec};
 use super::recsim::{GlobInstId, NspgiId, NspgiVec};
 use super::top_sim::{GadgetExecCycle, GlobSimCycle, GlobSimulationState};
 use super::WireValue;
+use crate::diagnostics::{Violation, ViolationKind};
 use crate::share_set::{ShareId, ShareSet};
-use anyhow::{bail, Result};
 use itertools::Itertools;
 use std::rc::Rc;
 
@@ -23,6 +24,8 @@ pub struct WireState {
     pub deterministic: bool,
     /// Last execution of each NSPGI this wire depends on.
     pub nspgi_dep: NspgiDep,
+    /// Value is stable during the clock cycle (no glitch).
+    pub glitch_free: bool,
 }
 
 // The GlobSimCycle refers to the "gadget exec ref lat + max_input_lat" cycle.
@@ -38,6 +41,7 @@ impl WireState {
             random: None,
             deterministic: false,
             nspgi_dep: Default::default(),
+            glitch_free: false,
         }
     }
     pub fn glitch_deterministic(&self) -> bool {
@@ -52,6 +56,10 @@ impl WireState {
     pub fn is_control(&self, value: WireValue) -> bool {
         self.glitch_deterministic() && self.value == Some(value)
     }
+    /// Deterministic with a known value, and does not glitch: gates glitches from other inputs.
+    pub fn is_stable_control(&self) -> bool {
+        self.glitch_free && self.glitch_deterministic() && self.value.is_some()
+    }
     pub fn negate(&self) -> Self {
         self.clone().with_value(self.value.map(|v| !v))
     }
@@ -77,10 +85,11 @@ impl WireState {
         self
     }
     pub fn stop_glitches(mut self) -> Self {
-        self.glitch_sensitivity = self.sensitivity;
+        self.glitch_sensitivity = self.sensitivity.clone();
+        self.glitch_free = true;
         self
     }
-    pub fn with_glitches(mut self, glitches: ShareSet) -> Self {
+    pub fn with_glitches(mut self, glitches: &ShareSet) -> Self {
         self.glitch_sensitivity = self.glitch_sensitivity.union(glitches);
         self
     }
@@ -96,24 +105,38 @@ impl WireState {
         // sensitivity is a subset of glitch-sensitivity
         assert_eq!(
             self.glitch_sensitivity,
-            self.glitch_sensitivity.union(self.sensitivity),
+            self.glitch_sensitivity.union(&self.sensitivity),
         );
         if self.deterministic {
             assert!(self.sensitivity.is_empty());
             assert!(self.random.is_none());
         }
+        if self.glitch_free {
+            assert_eq!(self.glitch_sensitivity, self.sensitivity);
+        }
     }
-    pub fn check_secure(&self) -> Result<()> {
-        if self.sensitivity.len() > 1 {
-            bail!(
-                "Wire is sensitive for multiple shares: {}.",
-                self.sensitivity
-            );
-        } else if self.glitch_sensitivity.len() > 1 {
-            bail!(
-                "Wire is glitch-sensitive for multiple shares: {}.",
-                self.glitch_sensitivity
-            );
+    /// Check the wire as a glitch-extended probe (see GlobSimulationState::probe).
+    pub fn check_secure(&self, sim_state: &mut GlobSimulationState) -> Result<(), Violation> {
+        if let Err(probes) = sim_state.probe(&self.sensitivity) {
+            Err(Violation::new(
+                ViolationKind::WireMultiShare,
+                format!(
+                    "Wire is sensitive for multiple shares: {}.",
+                    self.sensitivity
+                ),
+            )
+            .with_shares([self.sensitivity.clone()])
+            .with_joint_probes(probes))
+        } else if let Err(probes) = sim_state.probe(&self.glitch_sensitivity) {
+            Err(Violation::new(
+                ViolationKind::WireMultiShare,
+                format!(
+                    "Wire is glitch-sensitive for multiple shares: {}.",
+                    self.glitch_sensitivity
+                ),
+            )
+            .with_shares([self.glitch_sensitivity.clone()])
+            .with_joint_probes(probes))
         } else {
             Ok(())
         }
@@ -137,9 +160,20 @@ impl CombBinary {
         &self,
         op0: &WireState,
         op1: &WireState,
-        sim_state: Option<&mut GlobSimulationState>,
+        mut sim_state: Option<&mut GlobSimulationState>,
         inst_id: GlobInstId,
     ) -> WireState {
+        let op1_negated;
+        let op1 = if self.inverted_b() {
+            // Same as a NOT gate before the base gate.
+            if let Some(sim_state) = sim_state.as_deref_mut() {
+                sim_state.leak_random(op1, inst_id);
+            }
+            op1_negated = op1.negate();
+            &op1_negated
+        } else {
+            op1
+        };
         let mut res = if op0.is_control(self.neutral())
             || self.absorb().is_some_and(|v| op1.is_control(v))
         {
@@ -148,10 +182,10 @@ impl CombBinary {
         {
             op0.clone()
         } else {
-            let sensitivity = op0.sensitivity.union(op1.sensitivity);
-            let glitch_sensitivity = op0.glitch_sensitivity.union(op1.glitch_sensitivity);
-            let value = self.opx(op0.value, op1.value);
-            if let Some(sim_state) = sim_state {
+            let sensitivity = op0.sensitivity.union(&op1.sensitivity);
+            let glitch_sensitivity = op0.glitch_sensitivity.union(&op1.glitch_sensitivity);
+            let value = self.base().opx(op0.value, op1.value);
+            if let Some(sim_state) = sim_state.as_deref_mut() {
                 sim_state.leak_random(op0, inst_id);
                 sim_state.leak_random(op1, inst_id);
             }
@@ -165,15 +199,224 @@ impl CombBinary {
                 random,
                 deterministic,
                 nspgi_dep,
+                glitch_free: false,
             };
             res.consistency_check();
             res
         };
-        // FIXME: handle glitch gating with stable deterministic value ?
-        res.glitch_sensitivity = op0.glitch_sensitivity.union(op1.glitch_sensitivity);
+        // Glitches of an input propagate, unless the other input is a stable control value
+        // (neutral or absorbing).
+        if op0.is_stable_control() && op0.value == Some(self.neutral()) {
+            res.glitch_free = op1.glitch_free;
+        } else if op1.is_stable_control() && op1.value == Some(self.neutral()) {
+            res.glitch_free = op0.glitch_free;
+        } else if self.absorb().is_some_and(|v| {
+            (op0.is_stable_control() && op0.value == Some(v))
+                || (op1.is_stable_control() && op1.value == Some(v))
+        }) {
+            res.glitch_free = true;
+        } else {
+            res.glitch_sensitivity = op0.glitch_sensitivity.union(&op1.glitch_sensitivity);
+            res.glitch_free = false;
+        }
+        if self.inverted() {
+            // Same as a NOT gate after the base gate.
+            if let Some(sim_state) = sim_state {
+                sim_state.leak_random(&res, inst_id);
+            }
+            res.negate()
+        } else {
+            res
+        }
+    }
+}
+impl Dff {
+    /// Value stored in the flip-flop at the end of the cycle, given its inputs and its currently
+    /// stored value.
+    pub fn sim(
+        &self,
+        inputs: &InputVec<Option<WireState>>,
+        stored: &WireState,
+        mut sim_state: Option<&mut GlobSimulationState>,
+        inst_id: GlobInstId,
+    ) -> WireState {
+        // Enable and reset are only sampled at the clock edge: their glitches do not matter, and a
+        // deterministic value selects the next state without mixing it with other inputs.
+        let sampled =
+            |input: &WireState, value: WireValue| input.deterministic && input.value == Some(value);
+        let d = inputs[InputId::from_usize(1)].as_ref().unwrap();
+        let next = match (self.enable, self.enable_input()) {
+            (Some(active), Some(enable_input)) => {
+                let enable = inputs[enable_input].as_ref().unwrap();
+                if sampled(enable, active) {
+                    d.clone()
+                } else if sampled(enable, !active) {
+                    stored.clone()
+                } else {
+                    // Same as a MUX in front of a DFF.
+                    let (op0, op1) = match active {
+                        WireValue::_1 => (stored, d),
+                        WireValue::_0 => (d, stored),
+                    };
+                    sim_mux(op0, op1, enable, sim_state.as_deref_mut(), inst_id)
+                }
+            }
+            _ => d.clone(),
+        };
+        match (self.reset, self.reset_input()) {
+            (Some((active, value)), Some(reset_input)) => {
+                let reset = inputs[reset_input].as_ref().unwrap();
+                if sampled(reset, active) {
+                    WireState::control().with_value(Some(value))
+                } else if sampled(reset, !active) {
+                    next
+                } else {
+                    // Same as an AND/OR gate in front of a DFF.
+                    let bgate = match (active, value) {
+                        (WireValue::_1, WireValue::_0) => CombBinary::AndNot,
+                        (WireValue::_0, WireValue::_0) => CombBinary::And,
+                        (WireValue::_1, WireValue::_1) => CombBinary::Or,
+                        (WireValue::_0, WireValue::_1) => CombBinary::OrNot,
+                    };
+                    bgate.sim(&next, reset, sim_state, inst_id)
+                }
+            }
+            _ => next,
+        }
+    }
+}
+
+impl Latch {
+    /// MUX between the stored value (op0) and D (op1), selected by the enable.
+    fn sim_mux(
+        &self,
+        inputs: &InputVec<Option<WireState>>,
+        stored: &WireState,
+        sim_state: Option<&mut GlobSimulationState>,
+        inst_id: GlobInstId,
+    ) -> WireState {
+        let enable = inputs[Self::ENABLE_INPUT].as_ref().unwrap();
+        let d = inputs[Self::D_INPUT].as_ref().unwrap();
+        let (op0, op1) = match self.enable {
+            WireValue::_1 => (stored, d),
+            WireValue::_0 => (d, stored),
+        };
+        sim_mux(op0, op1, enable, sim_state, inst_id)
+    }
+    /// Output of the latch: D when transparent, and the stored value when the enable is inactive.
+    /// `stored` is the stored value as seen on the output (i.e., without glitches if the latch
+    /// stops them).
+    pub fn sim_output(
+        &self,
+        inputs: &InputVec<Option<WireState>>,
+        stored: &WireState,
+        sim_state: Option<&mut GlobSimulationState>,
+        inst_id: GlobInstId,
+    ) -> WireState {
+        self.sim_mux(inputs, stored, sim_state, inst_id)
+    }
+    /// Value stored in the latch at the end of the cycle.
+    pub fn sim_stored(
+        &self,
+        inputs: &InputVec<Option<WireState>>,
+        stored: &WireState,
+        sim_state: Option<&mut GlobSimulationState>,
+        inst_id: GlobInstId,
+    ) -> WireState {
+        // The latch closes at the end of the cycle: as for a DFF, glitches of the enable do not
+        // matter.
+        let enable = inputs[Self::ENABLE_INPUT].as_ref().unwrap();
+        if enable.deterministic && enable.value == Some(self.enable) {
+            inputs[Self::D_INPUT].clone().unwrap()
+        } else if enable.deterministic && enable.value == Some(!self.enable) {
+            stored.clone()
+        } else {
+            self.sim_mux(inputs, stored, sim_state, inst_id)
+        }
+    }
+}
+
+impl Lut {
+    pub fn sim(
+        &self,
+        inputs: &[&WireState],
+        sim_state: Option<&mut GlobSimulationState>,
+        inst_id: GlobInstId,
+    ) -> WireState {
+        // Inputs the output actually depends on, once the inputs that satisfy fixed are fixed to
+        // their value.
+        let relevant_inputs = |fixed: fn(&WireState) -> bool| {
+            let lut = self.restrict(
+                &inputs
+                    .iter()
+                    .map(|op| if fixed(op) { op.value } else { None })
+                    .collect::<Vec<_>>(),
+            );
+            (0..self.n_inputs())
+                .filter(|i| lut.depends_on(*i))
+                .collect::<Vec<_>>()
+        };
+        let is_control = |op: &WireState| op.glitch_deterministic() && op.value.is_some();
+        let values = inputs.iter().map(|op| op.value).collect::<Vec<_>>();
+        let value = self.opx(&values);
+        let mut res = match relevant_inputs(is_control).as_slice() {
+            // The output is fixed by the control inputs.
+            [] => WireState {
+                nspgi_dep: inputs
+                    .iter()
+                    .filter(|op| is_control(op))
+                    .fold(NspgiDep::default(), |dep, op| dep.max(&op.nspgi_dep)),
+                ..WireState::control().with_value(value)
+            },
+            // Buffer or inverter of a single input.
+            [i] => {
+                let mut values = values.clone();
+                values[*i] = Some(WireValue::_0);
+                if self.opx(&values) == Some(WireValue::_0) {
+                    inputs[*i].clone()
+                } else {
+                    if let Some(sim_state) = sim_state {
+                        sim_state.leak_random(inputs[*i], inst_id);
+                    }
+                    inputs[*i].negate()
+                }
+            }
+            relevant => {
+                if let Some(sim_state) = sim_state {
+                    for i in relevant {
+                        sim_state.leak_random(inputs[*i], inst_id);
+                    }
+                }
+                WireState {
+                    sensitivity: relevant
+                        .iter()
+                        .fold(ShareSet::empty(), |s, i| s.union(&inputs[*i].sensitivity)),
+                    glitch_sensitivity: ShareSet::empty(),
+                    value,
+                    random: None,
+                    deterministic: relevant.iter().all(|i| inputs[*i].deterministic),
+                    nspgi_dep: relevant
+                        .iter()
+                        .fold(NspgiDep::default(), |dep, i| dep.max(&inputs[*i].nspgi_dep)),
+                    glitch_free: false,
+                }
+            }
+        };
+        // Glitches of an input propagate, unless it is gated by stable control inputs.
+        let glitch_inputs = relevant_inputs(WireState::is_stable_control);
+        res.glitch_sensitivity = glitch_inputs.iter().fold(res.sensitivity.clone(), |s, i| {
+            s.union(&inputs[*i].glitch_sensitivity)
+        });
+        res.glitch_free = match glitch_inputs.as_slice() {
+            [] => true,
+            [i] => inputs[*i].glitch_free,
+            _ => false,
+        };
+        res.consistency_check();
         res
     }
 }
+
 pub fn sim_mux(
     op0: &WireState,
     op1: &WireState,
@@ -181,14 +424,24 @@ pub fn sim_mux(
     sim_state: Option<&mut GlobSimulationState>,
     inst_id: GlobInstId,
 ) -> WireState {
-    // FIXME: handle glitch gating with stable deterministic value ?
     op0.consistency_check();
     op1.consistency_check();
     ops.consistency_check();
-    let res = if ops.is_control(WireValue::_0) {
-        op0.clone().with_glitches(op1.glitch_sensitivity)
+    // With a stable control value, the non-selected input cannot glitch on the output.
+    let res = if ops.is_stable_control() && ops.value == Some(WireValue::_0) {
+        op0.clone()
+    } else if ops.is_stable_control() && ops.value == Some(WireValue::_1) {
+        op1.clone()
+    } else if ops.is_control(WireValue::_0) {
+        WireState {
+            glitch_free: false,
+            ..op0.clone().with_glitches(&op1.glitch_sensitivity)
+        }
     } else if ops.is_control(WireValue::_1) {
-        op1.clone().with_glitches(op0.glitch_sensitivity)
+        WireState {
+            glitch_free: false,
+            ..op1.clone().with_glitches(&op0.glitch_sensitivity)
+        }
     } else {
         // Here we are a bit pessimistic wrt randomness, some cases might not be leakage, but that
         // should not be an issue in practice: mux without deterministic control should not be used
@@ -201,12 +454,12 @@ pub fn sim_mux(
         WireState {
             sensitivity: op0
                 .sensitivity
-                .union(op1.sensitivity)
-                .union(ops.sensitivity),
+                .union(&op1.sensitivity)
+                .union(&ops.sensitivity),
             glitch_sensitivity: op0
                 .glitch_sensitivity
-                .union(op1.glitch_sensitivity)
-                .union(ops.glitch_sensitivity),
+                .union(&op1.glitch_sensitivity)
+                .union(&ops.glitch_sensitivity),
             value: ops.value.and_then(|s| match s {
                 WireValue::_0 => op0.value,
                 WireValue::_1 => op1.value,
@@ -214,6 +467,7 @@ pub fn sim_mux(
             random: (op0.random == op1.random).then_some(op0.random).flatten(),
             deterministic: ops.deterministic && op0.deterministic && op1.deterministic,
             nspgi_dep: op0.nspgi_dep.max(&op1.nspgi_dep).max(&ops.nspgi_dep),
+            glitch_free: false,
         }
     };
     res.consistency_check();
@@ -0,0 +1,167 @@
+//! Stimulus file (--stimulus), giving the values of the top-level signals without a testbench
+//! simulation.
+//!
+//! The file is in TOML format, with the number of simulated cycles and the values of the
+//! signals, e.g.,
+//! ```toml
+//! cycles = 20
+//!
+//! [signals]
+//! rst = { values = [1], repeat = [0] }
+//! in_valid = { values = [0, 0], repeat = [1, 0, 0, 0] }
+//! mode = "01"
+//! ```
+//! Each signal is a port (or another wire) of the top-level module. Its value at each cycle is
+//! given by the `values` list, then by the `repeat` pattern, which is repeated until the end of
+//! the simulation (if there is no pattern, the last value is kept). A signal may also be given
+//! by a single value, or by a list of values.
+//! A value is an integer, or a binary string (most significant bit first, which may contain 'x'
+//! digits) that is extended as in vcd files.
+use super::WireValue;
+use crate::type_utils::new_id;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+
+new_id!(SignalId, SignalVec);
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StimulusDesc {
+    cycles: usize,
+    #[serde(default)]
+    signals: BTreeMap<String, SignalDesc>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+enum SignalDesc {
+    Constant(ValueDesc),
+    Values(Vec<ValueDesc>),
+    Pattern {
+        #[serde(default)]
+        values: Vec<ValueDesc>,
+        #[serde(default)]
+        repeat: Vec<ValueDesc>,
+    },
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+enum ValueDesc {
+    Int(u64),
+    Bits(String),
+}
+
+/// Value of a signal at a cycle (least significant bit first).
+#[derive(Debug, Clone)]
+struct Value {
+    bits: Vec<Option<WireValue>>,
+    /// Value of the bits that are not given.
+    extension: Option<WireValue>,
+}
+
+#[derive(Debug, Clone)]
+struct Signal {
+    values: Vec<Value>,
+    repeat: Vec<Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StimulusSpec {
+    n_cycles: usize,
+    names: BTreeMap<String, SignalId>,
+    signals: SignalVec<Signal>,
+}
+
+impl StimulusSpec {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read stimulus file '{}'.", path))?;
+        let desc: StimulusDesc = toml::from_str(&content)
+            .with_context(|| format!("Could not parse stimulus file '{}'.", path))?;
+        let mut names = BTreeMap::new();
+        let mut signals = SignalVec::new();
+        for (name, signal) in desc.signals {
+            let signal = Signal::new(signal)
+                .with_context(|| format!("Invalid signal {} in stimulus file.", name))?;
+            names.insert(name, signals.push(signal));
+        }
+        Ok(Self {
+            n_cycles: desc.cycles,
+            names,
+            signals,
+        })
+    }
+    pub fn n_cycles(&self) -> usize {
+        self.n_cycles
+    }
+    pub fn signal(&self, name: &str) -> Option<SignalId> {
+        self.names.get(name).copied()
+    }
+    pub fn signal_names(&self) -> impl Iterator<Item = &str> {
+        self.names.keys().map(String::as_str)
+    }
+    /// Value of a bit of a signal at the given cycle.
+    pub fn value(&self, signal: SignalId, offset: usize, cycle: usize) -> Option<WireValue> {
+        let signal = &self.signals[signal];
+        let value = if cycle < signal.values.len() {
+            &signal.values[cycle]
+        } else if signal.repeat.is_empty() {
+            signal.values.last().unwrap()
+        } else {
+            &signal.repeat[(cycle - signal.values.len()) % signal.repeat.len()]
+        };
+        value.bits.get(offset).copied().unwrap_or(value.extension)
+    }
+}
+
+impl Signal {
+    fn new(desc: SignalDesc) -> Result<Self> {
+        let (values, repeat) = match desc {
+            SignalDesc::Constant(value) => (vec![value], vec![]),
+            SignalDesc::Values(values) => (values, vec![]),
+            SignalDesc::Pattern { values, repeat } => (values, repeat),
+        };
+        if values.is_empty() && repeat.is_empty() {
+            bail!("No value is given.");
+        }
+        let parse =
+            |values: Vec<ValueDesc>| values.into_iter().map(Value::new).collect::<Result<_>>();
+        Ok(Self {
+            values: parse(values)?,
+            repeat: parse(repeat)?,
+        })
+    }
+}
+
+impl Value {
+    fn new(desc: ValueDesc) -> Result<Self> {
+        Ok(match desc {
+            ValueDesc::Int(value) => Self {
+                bits: (0..u64::BITS)
+                    .map(|i| Some(WireValue::from((value >> i) & 1 == 1)))
+                    .collect(),
+                extension: Some(WireValue::_0),
+            },
+            ValueDesc::Bits(bits) => {
+                let bits = bits
+                    .chars()
+                    .rev()
+                    .map(|c| match c {
+                        '0' => Ok(Some(WireValue::_0)),
+                        '1' => Ok(Some(WireValue::_1)),
+                        'x' | 'X' => Ok(None),
+                        _ => bail!("Invalid digit '{}' in value (expected 0, 1 or x).", c),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let Some(msb) = bits.last() else {
+                    bail!("Empty value.");
+                };
+                // As in vcd files, the value is extended with 'x' if its first digit is 'x', and
+                // with 0 otherwise.
+                let extension = msb.map(|_| WireValue::_0);
+                Self { bits, extension }
+            }
+        })
+    }
+}
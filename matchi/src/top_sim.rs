@@ -1,20 +1,28 @@
-use super::gadget::top::{ActiveWireId, ActiveWireVec, LatencyCondition};
+use super::clk_vcd::{VarOffsetId, WaveformParser};
+use super::cross_check::CrossCheck;
+use super::gadget::top::{ActiveWireVec, LatencyCondition, TopGadget};
 use super::gadget::{Latency, PortRole, RndPortVec};
-use super::module::{ConnectionId, InputId, InputVec, WireName};
+use super::module::gates::{CombUnitary, Gate};
+use super::module::{
+    ConnectionId, InputId, InputVec, InstanceType, Module, OutputId, WireId, WireName,
+};
 use super::netlist::ModList;
 use super::recsim::{
     EvalInstanceIds, Evaluator, EvaluatorState, GlobInstId, ModuleEvaluator, ModuleState, NspgiId,
     NspgiVec,
 };
 use super::simulation::WireState;
+use super::stimulus::{SignalId, StimulusSpec};
 use super::WireValue;
 use super::{ModuleId, Netlist};
+use crate::diagnostics::{Diagnostic, Violation, ViolationKind};
 use crate::share_set::ShareSet;
 use crate::type_utils::new_id;
 use crate::type_utils::ExtendIdx;
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, Context, Result};
 use std::collections::VecDeque;
 use std::fmt::Write;
+use yosys_netlist_json as yosys;
 
 new_id!(GlobSimCycle, GlobSimCycleVec, GlobSimCycleSlice);
 
@@ -45,13 +53,62 @@ impl GadgetExecCycle {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Simulator {
     module_id: ModuleId,
+    gadget: TopGadget,
     evaluator: ModuleEvaluator,
-    vcd_states: super::clk_vcd::VcdParsedStates,
-    input_vcd_ids: InputVec<super::clk_vcd::VarOffsetId>,
-    active_wire_ids: ActiveWireVec<super::clk_vcd::VarOffsetId>,
+    stimulus: Stimulus,
+    active_sources: ActiveWireVec<ActiveSource>,
+    /// Wires to evaluate to get the value of the 'matchi_active' signals taken from the simulation
+    /// of the netlist.
+    active_cone: Vec<WireId>,
+    /// Latency of the last output of an execution, if the outputs have fixed latencies.
+    exec_end_lat: Option<Latency>,
+    /// State elements that drive the output ports: they may hold the outputs after the end of
+    /// an execution.
+    output_state: Vec<OutputState>,
+}
+
+/// Source of the values of the input ports and of the 'matchi_active' signals.
+#[derive(Debug)]
+enum Stimulus {
+    /// Values parsed from the simulation vcd.
+    Vcd {
+        vcd_states: Box<super::clk_vcd::VcdParsedStates>,
+        input_vcd_ids: InputVec<VarOffsetId>,
+        cross_check: Option<CrossCheck>,
+    },
+    /// Values given by a stimulus file.
+    Spec {
+        spec: StimulusSpec,
+        /// Signal and offset of each input port.
+        input_signals: InputVec<Option<(SignalId, usize)>>,
+    },
+    /// A single execution starting at cycle 0, all input values are 'x'.
+    /// Used to verify isolated gadgets.
+    SingleExec { n_cycles: usize },
+}
+
+/// State element (DFF, latch or stage of a pipeline gadget) that drives an output port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct OutputState {
+    path: Vec<String>,
+    stage: Option<Latency>,
+}
+
+/// Source of the value of a 'matchi_active' signal.
+#[derive(Debug, Clone, Copy)]
+enum ActiveSource {
+    /// Input port of the top-level module.
+    Input(InputId),
+    /// Given in the stimulus file.
+    Spec(SignalId, usize),
+    /// Taken from the value simulation of the top-level module, and cross-checked with the vcd
+    /// (if it is in the vcd).
+    Simulation(WireId, Option<VarOffsetId>),
+    /// Taken from the vcd.
+    Vcd(VarOffsetId),
 }
 
 #[derive(Debug, Clone, Default)]
@@ -117,7 +174,102 @@ impl RndTracker {
     }
 }
 
+/// Share sets observed by glitch-extended probes that observe multiple shares, for the
+/// verification at a probing order t below d-1 (--order).
+///
+/// By PINI composition, every other probe observes at most one share (index). Therefore, k
+/// multi-share probes observing S_1, ..., S_k, together with t-k other probes, may observe all
+/// the d shares of a sharing if |S_1 ∪ ... ∪ S_k| + t-k >= d.
+/// An observed set that is a subset of another one is not kept, since the larger set can always
+/// be probed instead. The observed sets are reset at the start of each execution.
 #[derive(Debug, Clone)]
+struct ProbedShares {
+    order: usize,
+    nshares: usize,
+    /// Observed sets of multiple shares.
+    observed: Vec<ShareSet>,
+}
+
+impl ProbedShares {
+    fn new(order: u32, nshares: u32) -> Self {
+        // At order d-1 (default), no probe may observe multiple shares.
+        Self {
+            order: order.max(1) as usize,
+            nshares: nshares as usize,
+            observed: vec![],
+        }
+    }
+    fn reset(&mut self) {
+        self.observed.clear();
+    }
+    /// Record a probe observing `shares`. If t probes may then observe all the shares, the probe
+    /// is not recorded, and the other observed sets that are jointly probed are returned.
+    fn observe(&mut self, shares: &ShareSet) -> Result<(), Vec<ShareSet>> {
+        if shares.len() <= 1 || self.observed.iter().any(|set| shares.subset_of(set)) {
+            return Ok(());
+        }
+        let mut chosen = vec![];
+        if self.covers(shares, 1, 0, &mut chosen) {
+            return Err(chosen
+                .into_iter()
+                .map(|i| self.observed[i].clone())
+                .collect());
+        }
+        self.observed.retain(|set| !set.subset_of(shares));
+        self.observed.push(shares.clone());
+        Ok(())
+    }
+    /// Whether `n_probes` probes observing `union`, jointly with observed sets from index `start`
+    /// and single-share probes, may observe all the shares. The indices of the observed sets that
+    /// are used are pushed to `chosen`.
+    ///
+    /// This is a search over the combinations of observed sets: each chosen set adds at least two
+    /// shares, hence at most min(t-1, (d-2)/2) sets are chosen, and the search visits
+    /// O(m^min(t-1, (d-2)/2)) combinations of the m observed sets in the worst case. Branches that
+    /// cannot reach d shares, even with the sets that add the most shares, are pruned.
+    fn covers(
+        &self,
+        union: &ShareSet,
+        n_probes: usize,
+        start: usize,
+        chosen: &mut Vec<usize>,
+    ) -> bool {
+        if union.len() + self.order >= self.nshares + n_probes {
+            return true;
+        }
+        if n_probes == self.order {
+            return false;
+        }
+        // A set adding g shares replaces a single-share probe, hence it observes g-1 more shares.
+        let mut gains = self.observed[start..]
+            .iter()
+            .map(|set| set.difference(union).len())
+            .filter(|gain| *gain >= 2)
+            .collect::<Vec<_>>();
+        gains.sort_unstable_by(|a, b| b.cmp(a));
+        let max_gain: usize = gains
+            .iter()
+            .take(self.order - n_probes)
+            .map(|gain| gain - 1)
+            .sum();
+        if union.len() + self.order + max_gain < self.nshares + n_probes {
+            return false;
+        }
+        for (i, set) in self.observed.iter().enumerate().skip(start) {
+            // A set adding at most one share does no better than a single-share probe.
+            if set.difference(union).len() >= 2 {
+                chosen.push(i);
+                if self.covers(&union.union(set), n_probes + 1, i + 1, chosen) {
+                    return true;
+                }
+                chosen.pop();
+            }
+        }
+        false
+    }
+}
+
+#[derive(Debug)]
 pub struct GlobSimulationState {
     /// For every top-level random port and every input latency, track where the corresponding
     /// random value is leaked, used and stored.
@@ -126,29 +278,44 @@ pub struct GlobSimulationState {
     current_cycle: GlobSimCycle,
     /// Last "valid" execution start cycle.
     last_exec_start: Option<GlobSimCycle>,
+    /// Number of executions started so far.
+    n_execs: usize,
+    /// The current cycle ends an execution (its last output latency has passed, or the
+    /// execution signal fell).
+    exec_end: bool,
     /// Last "pipeline bubble" execution. Sim
     pub last_nonsensitive_exec: NspgiVec<Option<GadgetExecCycle>>,
+    /// Violations found in the current cycle, with their message in context, if they are
+    /// collected (otherwise, they are returned as errors).
+    violations: Option<Vec<(Violation, String)>>,
+    probed_shares: ProbedShares,
 }
 
 #[derive(Debug, Clone)]
 pub struct SimulationState {
     eval_state: EvaluatorState,
+    /// Value of the 'matchi_active' signals.
+    active: ActiveWireVec<bool>,
 }
 
 impl Simulator {
+    /// Simulator for the given gadget (the top-level gadget, or an isolated loopy gadget) whose
+    /// inputs are taken from a simulation vcd, at the dut_path scope.
     pub fn new(
         netlist: &Netlist,
-        vcd_parser: vcd::Parser<impl std::io::BufRead>,
+        gadget: &TopGadget,
+        yosys_netlist: &yosys::Netlist,
+        vcd_parser: WaveformParser<impl std::io::BufRead + std::io::Seek + Send + 'static>,
         dut_path: &[String],
     ) -> Result<Self> {
-        let module_id = netlist.top_gadget.module_id;
+        let module_id = gadget.module_id;
         let module = netlist.module(module_id);
         let mut clk_path = dut_path.to_owned();
         clk_path.push(
             module
                 .clock
                 .as_ref()
-                .ok_or_else(|| anyhow!("Top-level gadget must have a clock."))?
+                .ok_or_else(|| anyhow!("Gadget {} must have a clock.", module.name))?
                 .name()
                 .to_owned(),
         );
@@ -165,54 +332,285 @@ impl Simulator {
             .map(|con_id| add_var(&module.ports[*con_id]))
             .collect::<Result<InputVec<_>>>()
             .with_context(|| "Error while looking up input ports in vcd.")?;
-        let active_wire_ids = netlist
-            .top_gadget
+        let active_sources = gadget
             .active_wires
             .iter()
-            .map(add_var)
+            .map(|wire_name| {
+                if let Some(input_id) = input_port(module, wire_name) {
+                    return Ok(ActiveSource::Input(input_id));
+                }
+                let var = add_var(wire_name);
+                match (
+                    var,
+                    module_wire_id(netlist, yosys_netlist, module_id, wire_name),
+                ) {
+                    (Ok(var), Ok(wire_id)) => Ok(ActiveSource::Simulation(wire_id, Some(var))),
+                    (Ok(var), Err(_)) => Ok(ActiveSource::Vcd(var)),
+                    (Err(_), Ok(wire_id)) => Ok(ActiveSource::Simulation(wire_id, None)),
+                    (Err(error), Err(wire_error)) => Err(error.context(wire_error)),
+                }
+            })
             .collect::<Result<ActiveWireVec<_>>>()
-            .with_context(|| "Error while looking up 'matchi_active' signals in vcd.")?;
-        let vcd_states = vcd_parsed_header.get_states()?;
-        Ok(Self {
-            module_id,
-            evaluator: ModuleEvaluator::new(
+            .with_context(|| "Error while looking up 'matchi_active' signals.")?;
+        let cross_check = crate::config::config().cross_check.then(|| {
+            let cross_check = CrossCheck::new(
+                &mut vcd_parsed_header,
+                dut_path,
                 module_id,
                 netlist,
-                vec![],
-                &mut EvalInstanceIds::new(),
-                vec![],
-            ),
-            vcd_states,
-            input_vcd_ids,
-            active_wire_ids,
-        })
+                yosys_netlist,
+            );
+            println!(
+                "cross-checking {} wires with the vcd",
+                cross_check.n_wires()
+            );
+            cross_check
+        });
+        let vcd_states = Box::new(vcd_parsed_header.get_states()?);
+        Ok(Self::from_stimulus(
+            module_id,
+            gadget.clone(),
+            netlist,
+            Stimulus::Vcd {
+                vcd_states,
+                input_vcd_ids,
+                cross_check,
+            },
+            active_sources,
+        ))
     }
-    fn vcd_avar(&self, aw_id: ActiveWireId, cycle: GlobSimCycle) -> bool {
-        self.vcd_states
-            .get_var_offset(self.active_wire_ids[aw_id], cycle.index())
-            == Some(WireValue::_1)
+    /// Simulator whose inputs are given by a stimulus file instead of a simulation vcd.
+    pub fn from_spec(
+        netlist: &Netlist,
+        yosys_netlist: &yosys::Netlist,
+        spec: StimulusSpec,
+    ) -> Result<Self> {
+        let module_id = netlist.top_gadget.module_id;
+        let module = netlist.module(module_id);
+        for name in spec.signal_names() {
+            module_wire_id(
+                netlist,
+                yosys_netlist,
+                module_id,
+                &WireName::single_port(name.to_owned()),
+            )
+            .context("Error while looking up signals of the stimulus file.")?;
+        }
+        let input_signals = module
+            .input_ports
+            .iter()
+            .map(|con_id| {
+                let wire_name = &module.ports[*con_id];
+                spec.signal(wire_name.name())
+                    .map(|signal| (signal, wire_name.offset))
+            })
+            .collect::<InputVec<_>>();
+        let active_sources = netlist
+            .top_gadget
+            .active_wires
+            .iter()
+            .map(|wire_name| {
+                Ok(if let Some(input_id) = input_port(module, wire_name) {
+                    ActiveSource::Input(input_id)
+                } else if let Some(signal) = spec.signal(wire_name.name()) {
+                    ActiveSource::Spec(signal, wire_name.offset)
+                } else {
+                    ActiveSource::Simulation(
+                        module_wire_id(netlist, yosys_netlist, module_id, wire_name)?,
+                        None,
+                    )
+                })
+            })
+            .collect::<Result<ActiveWireVec<_>>>()
+            .with_context(|| "Error while looking up 'matchi_active' signals.")?;
+        Ok(Self::from_stimulus(
+            module_id,
+            netlist.top_gadget.clone(),
+            netlist,
+            Stimulus::Spec {
+                spec,
+                input_signals,
+            },
+            active_sources,
+        ))
+    }
+    /// Simulator for the verification of an isolated pipeline gadget: a single execution of the
+    /// gadget, without any other execution before or after it.
+    pub fn new_isolated(netlist: &Netlist, module_id: ModuleId) -> Result<Self> {
+        let module = netlist.module(module_id);
+        let gadget = netlist
+            .gadget(module_id)
+            .ok_or_else(|| anyhow!("Module {} is not a pipeline gadget.", module.name))?;
+        let top_gadget = TopGadget::from_pipeline(gadget, module);
+        Ok(Self::from_stimulus(
+            module_id,
+            top_gadget,
+            netlist,
+            Stimulus::SingleExec {
+                n_cycles: gadget.max_latency.index() + 1,
+            },
+            ActiveWireVec::new(),
+        ))
+    }
+    fn from_stimulus(
+        module_id: ModuleId,
+        gadget: TopGadget,
+        netlist: &Netlist,
+        stimulus: Stimulus,
+        active_sources: ActiveWireVec<ActiveSource>,
+    ) -> Self {
+        let module = netlist.module(module_id);
+        let exec_end_lat = module
+            .output_ports
+            .iter()
+            .filter(|con_id| matches!(gadget.port_roles[**con_id], PortRole::Share(_)))
+            .map(|con_id| match &gadget.latency[*con_id] {
+                Some(LatencyCondition::Lats(lats)) => lats.iter().copied().max(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .and_then(|lats| lats.into_iter().max());
+        let mut output_state = vec![];
+        for out in module.output_ports.indices() {
+            find_output_state(netlist, module_id, out, &mut vec![], &mut output_state);
+        }
+        let evaluator = ModuleEvaluator::new(
+            module_id,
+            netlist,
+            vec![],
+            &mut EvalInstanceIds::new(),
+            vec![],
+        );
+        let active_wires = active_sources
+            .iter()
+            .filter_map(|source| match source {
+                ActiveSource::Simulation(wire_id, _) => Some(*wire_id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let active_cone = evaluator.value_cone(&active_wires, netlist);
+        Self {
+            module_id,
+            gadget,
+            exec_end_lat,
+            output_state,
+            evaluator,
+            stimulus,
+            active_sources,
+            active_cone,
+        }
+    }
+    /// Value of the 'matchi_active' signals at the given cycle.
+    /// Mismatches between the simulation of the netlist and the vcd are reported in glob_state.
+    fn active_values(
+        &self,
+        prev_state: &SimulationState,
+        glob_state: &mut GlobSimulationState,
+        netlist: &Netlist,
+    ) -> Result<ActiveWireVec<bool>> {
+        let cycle = glob_state.current_cycle;
+        // The signals of the netlist are computed by a value simulation of their cone (which does
+        // not depend on the activity of the inputs).
+        let value_state = self
+            .active_sources
+            .iter()
+            .any(|source| matches!(source, ActiveSource::Simulation(..)))
+            .then(|| self.value_simulation(prev_state, cycle, netlist));
+        self.active_sources
+            .iter_enumerated()
+            .map(|(aw_id, source)| {
+                let value = match source {
+                    ActiveSource::Input(input_id) => self.input_value(*input_id, cycle),
+                    ActiveSource::Spec(signal, offset) => {
+                        let Stimulus::Spec { spec, .. } = &self.stimulus else {
+                            unreachable!("Stimulus file signal without stimulus file.");
+                        };
+                        spec.value(*signal, *offset, cycle.index())
+                    }
+                    ActiveSource::Simulation(wire_id, var) => {
+                        let value = value_state.as_ref().unwrap().module().wire_states[*wire_id]
+                            .as_ref()
+                            .and_then(|wire_state| wire_state.value);
+                        let vcd_value = var.and_then(|var| self.vcd_value(var, cycle));
+                        match (value, vcd_value) {
+                            (Some(value), Some(vcd_value)) if value != vcd_value => {
+                                glob_state.report(Err(Violation::new(
+                                    ViolationKind::ActiveMismatch,
+                                    format!(
+                                        "'matchi_active' signal {} is {} in the vcd at cycle {}, but it is {} in the simulation of the netlist.",
+                                        self.gadget.active_wires[aw_id],
+                                        u8::from(bool::from(vcd_value)),
+                                        cycle,
+                                        u8::from(bool::from(value)),
+                                    ),
+                                )
+                                .on_wire(&self.gadget.active_wires[aw_id])
+                                .into()))?;
+                                Some(value)
+                            }
+                            (value, vcd_value) => value.or(vcd_value),
+                        }
+                    }
+                    ActiveSource::Vcd(var) => self.vcd_value(*var, cycle),
+                };
+                Ok(value == Some(WireValue::_1))
+            })
+            .collect()
+    }
+    fn vcd_value(&self, var: VarOffsetId, cycle: GlobSimCycle) -> Option<WireValue> {
+        let Stimulus::Vcd { vcd_states, .. } = &self.stimulus else {
+            unreachable!("Vcd signal without vcd.");
+        };
+        vcd_states.get_var_offset(var, cycle.index())
+    }
+    /// Simulation of the cycle where all inputs are control signals, to get the values of the
+    /// 'matchi_active' signals computed by the netlist (only their cone is evaluated).
+    fn value_simulation(
+        &self,
+        prev_state: &SimulationState,
+        cycle: GlobSimCycle,
+        netlist: &Netlist,
+    ) -> EvaluatorState {
+        let module = netlist.module(self.module_id);
+        let mut eval_state = self.evaluator.init_next(&prev_state.eval_state, netlist);
+        for input_id in module.input_ports.indices() {
+            let input_state = WireState::control().with_value(self.input_value(input_id, cycle));
+            self.evaluator
+                .set_input(&mut eval_state, input_id, input_state, netlist);
+        }
+        self.evaluator
+            .eval_cone(&mut eval_state, &self.active_cone, netlist);
+        eval_state
+    }
+    fn input_value(&self, input_id: InputId, cycle: GlobSimCycle) -> Option<WireValue> {
+        match &self.stimulus {
+            Stimulus::Vcd {
+                vcd_states,
+                input_vcd_ids,
+                ..
+            } => vcd_states.get_var_offset(input_vcd_ids[input_id], cycle.index()),
+            Stimulus::Spec {
+                spec,
+                input_signals,
+                ..
+            } => input_signals[input_id]
+                .and_then(|(signal, offset)| spec.value(signal, offset, cycle.index())),
+            Stimulus::SingleExec { .. } => None,
+        }
     }
     fn con_valid(
         &self,
         con_id: ConnectionId,
-        netlist: &Netlist,
         cycle: GlobSimCycle,
         last_exec_start: Option<GlobSimCycle>,
+        active: &ActiveWireVec<bool>,
     ) -> Option<bool> {
-        netlist.top_gadget.latency[con_id]
+        let exec_cycle = last_exec_start
+            .and_then(|start| cycle.checked_sub(start))
+            .map(|exec_cycle| exec_cycle.index());
+        self.gadget.latency[con_id]
             .as_ref()
-            .map(|lat_cond| match lat_cond {
-                LatencyCondition::Always => true,
-                LatencyCondition::Never => false,
-                LatencyCondition::Lats(lats) => (|| -> Option<_> {
-                    lats.binary_search(&Latency::from_usize(
-                        cycle.checked_sub(last_exec_start?)?.index(),
-                    ))
-                    .ok()
-                })()
-                .is_some(),
-                LatencyCondition::OnActive(sim_signal) => self.vcd_avar(sim_signal.0, cycle),
-            })
+            .map(|lat_cond| lat_cond.is_valid(exec_cycle, active))
     }
     fn gadget_vcd_input(
         &self,
@@ -220,15 +618,14 @@ impl Simulator {
         netlist: &Netlist,
         cycle: GlobSimCycle,
         last_exec_start: Option<GlobSimCycle>,
+        active: &ActiveWireVec<bool>,
     ) -> WireState {
         let module = netlist.module(self.module_id);
-        let gadget = &netlist.top_gadget;
-        let value: Option<super::WireValue> = self
-            .vcd_states
-            .get_var_offset(self.input_vcd_ids[input_id], cycle.index());
+        let gadget = &self.gadget;
+        let value = self.input_value(input_id, cycle);
         let con_id = module.input_ports[input_id];
-        let valid = self.con_valid(con_id, netlist, cycle, last_exec_start);
-        if valid == Some(true) && value.is_none() {
+        let valid = self.con_valid(con_id, cycle, last_exec_start, active);
+        if valid == Some(true) && value.is_none() && matches!(self.stimulus, Stimulus::Vcd { .. }) {
             println!(
                 "Warning: input {} is annotated as valid, but simulation value is 'x'.",
                 module.ports[con_id]
@@ -252,9 +649,27 @@ impl Simulator {
         );
         */
     }
-    pub fn simu<'s>(&'s self, netlist: &'s Netlist, n_cycles: GlobSimCycle) -> SimuIter<'s> {
-        let simu_state = self.new_state(self.evaluator.x_state(netlist));
-        SimuIter::new(self, simu_state, netlist, n_cycles)
+    pub fn simu<'s>(&'s mut self, netlist: &'s Netlist) -> SimuIter<'s> {
+        let simu_state = SimulationState {
+            eval_state: self.evaluator.x_state(netlist),
+            active: ActiveWireVec::from_vec(vec![false; self.gadget.active_wires.len()]),
+        };
+        SimuIter::new(self, simu_state, netlist)
+    }
+    /// Make the stimulus available up to the given cycle, returns false if the stimulus has less
+    /// cycles.
+    fn load_stimulus(&mut self, cycle: GlobSimCycle) -> Result<bool> {
+        match &mut self.stimulus {
+            Stimulus::Vcd { vcd_states, .. } => vcd_states.load(cycle.index()),
+            Stimulus::Spec { spec, .. } => Ok(cycle.index() < spec.n_cycles()),
+            Stimulus::SingleExec { n_cycles } => Ok(cycle.index() < *n_cycles),
+        }
+    }
+    /// Discard the stimulus that is not needed anymore for the simulation of the given cycle.
+    fn discard_stimulus(&mut self, cycle: GlobSimCycle) {
+        if let Stimulus::Vcd { vcd_states, .. } = &mut self.stimulus {
+            vcd_states.discard_before(cycle.index());
+        }
     }
     fn next(
         &self,
@@ -264,39 +679,77 @@ impl Simulator {
     ) -> Result<SimulationState> {
         let module = netlist.module(self.module_id);
         let cycle = glob_state.current_cycle;
-        if let Some(ea) = netlist.top_gadget.exec_active {
-            let exec_active = self.vcd_avar(ea, cycle);
-            let past_exec_active = cycle > 0 && self.vcd_avar(ea, cycle - 1);
+        let active = self.active_values(prev_state, glob_state, netlist)?;
+        if let Some(ea) = self.gadget.exec_active {
+            let exec_active = active[ea];
+            let past_exec_active = cycle > 0 && prev_state.active[ea];
             if exec_active && !past_exec_active {
                 glob_state.last_exec_start = Some(cycle);
+                glob_state.n_execs += 1;
+                glob_state.probed_shares.reset();
             }
+            glob_state.exec_end = match (self.exec_end_lat, glob_state.last_exec_start) {
+                (Some(lat), Some(start)) => cycle == start + lat.index(),
+                (Some(_), None) => false,
+                (None, _) => !exec_active && past_exec_active,
+            };
         }
         let mut eval_state = self.evaluator.init_next(&prev_state.eval_state, netlist);
         for input_id in module.input_ports.indices() {
-            let input_state =
-                self.gadget_vcd_input(input_id, netlist, cycle, glob_state.last_exec_start);
+            let input_state = self.gadget_vcd_input(
+                input_id,
+                netlist,
+                cycle,
+                glob_state.last_exec_start,
+                &active,
+            );
             self.evaluator
                 .set_input(&mut eval_state, input_id, input_state.clone(), netlist);
         }
         self.evaluator
             .eval_finish(&mut eval_state, Some(glob_state), netlist);
+        if let Stimulus::Vcd {
+            vcd_states,
+            cross_check: Some(cross_check),
+            ..
+        } = &self.stimulus
+        {
+            cross_check.check(eval_state.module(), vcd_states, cycle.index())?;
+        }
         //self.evaluator.debug_state(&eval_state, netlist);
-        Ok(self.new_state(eval_state))
+        Ok(SimulationState { eval_state, active })
     }
-    fn new_glob_state(&self, netlist: &Netlist) -> GlobSimulationState {
-        let gadget = &netlist.top_gadget;
+    fn new_glob_state(&self, collect_violations: bool) -> GlobSimulationState {
+        let last_exec_start = match self.stimulus {
+            Stimulus::Vcd { .. } | Stimulus::Spec { .. } => None,
+            Stimulus::SingleExec { .. } => Some(GlobSimCycle::from_raw(0)),
+        };
         GlobSimulationState {
-            random_status: RndPortVec::from_vec(vec![RndTracker::new(); gadget.rnd_ports.len()]),
+            random_status: RndPortVec::from_vec(vec![
+                RndTracker::new();
+                self.gadget.rnd_ports.len()
+            ]),
             current_cycle: GlobSimCycle::from_raw(0),
-            last_exec_start: None,
+            last_exec_start,
+            n_execs: usize::from(last_exec_start.is_some()),
+            exec_end: false,
             last_nonsensitive_exec: NspgiVec::new(),
+            violations: collect_violations.then(Vec::new),
+            probed_shares: ProbedShares::new(
+                crate::config::config()
+                    .order
+                    .unwrap_or(self.gadget.nshares.saturating_sub(1)),
+                self.gadget.nshares,
+            ),
         }
     }
-    fn new_state(&self, eval_state: EvaluatorState) -> SimulationState {
-        SimulationState { eval_state }
-    }
-    pub fn n_cycles(&self) -> usize {
-        self.vcd_states.len()
+    /// Number of simulated cycles, if it is known before the simulation.
+    pub fn n_cycles(&self) -> Option<usize> {
+        match &self.stimulus {
+            Stimulus::Vcd { .. } => None,
+            Stimulus::Spec { spec, .. } => Some(spec.n_cycles()),
+            Stimulus::SingleExec { n_cycles } => Some(*n_cycles),
+        }
     }
 }
 
@@ -307,6 +760,24 @@ impl SimulationState {
 }
 
 impl GlobSimulationState {
+    /// Handle the result of a security check: if violations are collected, record the violation
+    /// and continue, otherwise return it.
+    pub fn report(&mut self, res: Result<()>) -> Result<()> {
+        match (res, &mut self.violations) {
+            (Err(error), Some(violations)) => {
+                let message = format!("{:#}", error);
+                let violation = error.downcast::<Violation>()?;
+                violations.push((violation, message));
+                Ok(())
+            }
+            (res, _) => res,
+        }
+    }
+    /// Check that a glitch-extended probe observing `shares` does not allow t probes to observe
+    /// all the shares of a sharing. On error, returns the share sets of the other probes.
+    pub fn probe(&mut self, shares: &ShareSet) -> Result<(), Vec<ShareSet>> {
+        self.probed_shares.observe(shares)
+    }
     pub fn leak_random(&mut self, wire: &WireState, inst: GlobInstId) {
         if let Some(rnd_source) = wire.random.as_ref() {
             let cur_lat = self.cur_lat();
@@ -353,30 +824,64 @@ impl GlobSimulationState {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct SimuIter<'a> {
     simu_state: SimulationState,
     glob_state: Option<GlobSimulationState>,
     netlist: &'a Netlist,
-    simulator: &'a Simulator,
-    n_cycles: GlobSimCycle,
+    simulator: &'a mut Simulator,
+    /// The current cycle is the last one.
+    last_cycle: bool,
+    collect_violations: bool,
 }
 
 impl<'a> SimuIter<'a> {
     fn new(
-        simulator: &'a Simulator,
+        simulator: &'a mut Simulator,
         simu_state: SimulationState,
         netlist: &'a Netlist,
-        n_cycles: GlobSimCycle,
     ) -> Self {
         Self {
             simu_state,
             glob_state: None,
             netlist,
             simulator,
-            n_cycles,
+            last_cycle: false,
+            collect_violations: false,
         }
     }
+    /// Collect violations instead of stopping at the first one (see take_diagnostics).
+    pub fn collecting_violations(mut self) -> Self {
+        self.collect_violations = true;
+        self
+    }
+    /// Violations found in the current cycle.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        let Some(glob_state) = self.glob_state.as_mut() else {
+            return vec![];
+        };
+        let cycle = glob_state.current_cycle;
+        glob_state
+            .violations
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(violation, message)| {
+                let path = violation.path.clone().or_else(|| {
+                    violation.inst.and_then(|inst| {
+                        self.simulator.evaluator.glob_inst2path(inst, self.netlist)
+                    })
+                });
+                Diagnostic {
+                    cycle,
+                    path,
+                    violation,
+                    message,
+                }
+            })
+            .collect()
+    }
     pub fn next(mut self) -> Result<Option<Self>> {
         let glob_state = if let Some(mut glob_state) = self.glob_state.take() {
             for rnd_tracker in glob_state.random_status.iter_mut() {
@@ -385,12 +890,16 @@ impl<'a> SimuIter<'a> {
             glob_state.current_cycle += 1;
             glob_state
         } else {
-            self.simulator.new_glob_state(self.netlist)
+            self.simulator.new_glob_state(self.collect_violations)
         };
         let glob_state = self.glob_state.insert(glob_state);
-        if glob_state.current_cycle == self.n_cycles {
+        let cycle = glob_state.current_cycle;
+        self.simulator.discard_stimulus(cycle);
+        if !self.simulator.load_stimulus(cycle)? {
             return Ok(None);
         }
+        // Look ahead to know if this is the last cycle.
+        self.last_cycle = !self.simulator.load_stimulus(cycle + 1)?;
         self.simu_state = self
             .simulator
             .next(&self.simu_state, glob_state, self.netlist)?;
@@ -401,19 +910,96 @@ impl<'a> SimuIter<'a> {
     }
     pub fn check(&mut self) -> Result<()> {
         if let Some(glob_state) = self.glob_state.as_mut() {
-            self.simulator.evaluator.check_safe_finish(
+            let res = self.simulator.evaluator.check_safe_finish(
                 &mut self.simu_state.eval_state,
                 glob_state,
                 self.netlist,
-            )?;
-            self.check_random_uses()?;
-            self.check_output_ports()?;
+            );
+            glob_state.report(res)?;
+            let violations = self
+                .random_uses_violations()
+                .into_iter()
+                .chain(self.output_ports_violations())
+                .chain(self.state_cleared_violations());
+            for violation in violations.collect::<Vec<_>>() {
+                self.glob_state
+                    .as_mut()
+                    .unwrap()
+                    .report(Err(violation.into()))?;
+            }
         }
         Ok(())
     }
-    fn check_random_uses(&self) -> Result<()> {
+    /// At the end of each execution, check that no secret remains stored in the circuit (except in
+    /// the state elements that hold the outputs).
+    /// Without execution signal, the check is done at the end of the simulation.
+    fn state_cleared_violations(&self) -> Vec<Violation> {
+        let glob_state = self.glob_state.as_ref().unwrap();
+        let exec_end = if self.simulator.gadget.exec_active.is_some() {
+            glob_state.exec_end
+        } else {
+            self.last_cycle
+        };
+        if crate::config::config().no_check_state_cleared
+            || matches!(self.simulator.stimulus, Stimulus::SingleExec { .. })
+            || !exec_end
+        {
+            return vec![];
+        }
+        let sensitive_state = self
+            .simulator
+            .evaluator
+            .sensitive_state(&self.simu_state.eval_state, self.netlist)
+            .into_iter()
+            .filter(|sensitive_state| {
+                !self.simulator.output_state.contains(&OutputState {
+                    path: sensitive_state.path.clone(),
+                    stage: sensitive_state.stage,
+                })
+            })
+            .collect::<Vec<_>>();
+        let execution = if self.simulator.gadget.exec_active.is_some() {
+            format!("execution {}", glob_state.n_execs.saturating_sub(1))
+        } else {
+            "the simulation".to_owned()
+        };
+        if sensitive_state.is_empty() {
+            vec![]
+        } else if glob_state.violations.is_some() {
+            sensitive_state
+                .into_iter()
+                .map(|sensitive_state| {
+                    Violation::new(
+                        ViolationKind::StateNotCleared,
+                        format!(
+                            "Secret remains in the state at the end of {} (cycle {}): {}",
+                            execution, glob_state.current_cycle, sensitive_state
+                        ),
+                    )
+                    .at_path(sensitive_state.path.join("."))
+                    .with_shares([sensitive_state.sensitivity])
+                })
+                .collect()
+        } else {
+            vec![Violation::new(
+                ViolationKind::StateNotCleared,
+                format!(
+                    "Secrets remain in the state at the end of {} (cycle {}):\n\t{}",
+                    execution,
+                    glob_state.current_cycle,
+                    sensitive_state
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("\n\t")
+                ),
+            )]
+        }
+    }
+    fn random_uses_violations(&self) -> Vec<Violation> {
+        let mut violations = vec![];
         let module = self.netlist.module(self.simulator.module_id);
-        let gadget = &self.netlist.top_gadget;
+        let gadget = &self.simulator.gadget;
         let glob_state = self.glob_state.as_ref().unwrap();
         for (rnd_port_id, rnd_uses) in glob_state.random_status.iter_enumerated() {
             for (lat, status) in rnd_uses.iter_enumerated() {
@@ -440,79 +1026,108 @@ impl<'a> SimuIter<'a> {
                         write!(use_string, "\n\tOther in:").unwrap();
                         write_uses(&status.leaks, &mut use_string);
                     }
-                    bail!(
-                        "Random input {} at cycle {} is used in multiple places:{}.",
-                        wire_name,
-                        lat,
-                        use_string,
+                    violations.push(
+                        Violation::new(
+                            ViolationKind::RandomnessReuse,
+                            format!(
+                                "Random input {} at cycle {} is used in multiple places:{}.",
+                                wire_name, lat, use_string,
+                            ),
+                        )
+                        .on_wire(wire_name),
                     );
                 }
             }
         }
-        Ok(())
+        violations
     }
-    fn check_output_ports(&self) -> Result<()> {
+    fn output_ports_violations(&self) -> Vec<Violation> {
         let module = self.netlist.module(self.simulator.module_id);
-        let gadget = &self.netlist.top_gadget;
+        module
+            .output_ports
+            .iter()
+            .filter_map(|con_id| {
+                self.check_output_port(*con_id)
+                    .err()
+                    .map(|violation| violation.on_wire(&module.ports[*con_id]))
+            })
+            .collect()
+    }
+    fn check_output_port(&self, con_id: ConnectionId) -> Result<(), Violation> {
+        let module = self.netlist.module(self.simulator.module_id);
+        let gadget = &self.simulator.gadget;
         let glob_state = self.glob_state.as_ref().unwrap();
-        for con_id in module.output_ports.iter() {
-            let valid = self.simulator.con_valid(
-                *con_id,
-                self.netlist,
-                glob_state.current_cycle,
-                glob_state.last_exec_start,
-            );
-            let wire_state = self.simu_state.eval_state.module().wire_states
-                [module.connection_wires[*con_id]]
-                .as_ref()
-                .unwrap();
-            match (&gadget.port_roles[*con_id], valid) {
-                (PortRole::Share(id), Some(true)) => {
-                    // FIXME: check glitch-sensitivity of outputs.
-                    //if !wire_state.glitch_sensitivity.subset_of(ShareSet::from(*id)) {
-                    if !wire_state.sensitivity.subset_of(ShareSet::from(*id)) {
-                        bail!(
+        let valid = self.simulator.con_valid(
+            con_id,
+            glob_state.current_cycle,
+            glob_state.last_exec_start,
+            &self.simu_state.active,
+        );
+        let wire_state = self.simu_state.eval_state.module().wire_states
+            [module.connection_wires[con_id]]
+            .as_ref()
+            .unwrap();
+        // For OPINI gadgets, the output shares are glitch-extended probes.
+        let probed_sensitivity =
+            if gadget.prop.has_output_probes() || crate::config::config().check_output_glitches {
+                &wire_state.glitch_sensitivity
+            } else {
+                &wire_state.sensitivity
+            };
+        match (&gadget.port_roles[con_id], valid) {
+            (PortRole::Share(id), Some(true)) => {
+                if !probed_sensitivity.subset_of(&ShareSet::from(*id)) {
+                    return Err(Violation::new(
+                        ViolationKind::OutputPort,
+                        format!(
                             "Output share {} is (glitch-)sensitive for shares {}.",
-                            module.ports[*con_id],
-                            //wire_state.glitch_sensitivity
-                            wire_state.sensitivity
-                        );
-                    } else if wire_state.sensitivity != ShareSet::from(*id) {
-                        println!(
-                            "Warning: output port {} is not sensitive, while marked as such.",
-                            module.ports[*con_id]
-                        )
-                    }
+                            module.ports[con_id], probed_sensitivity
+                        ),
+                    )
+                    .with_shares([probed_sensitivity.clone()]));
+                } else if wire_state.sensitivity != ShareSet::from(*id) {
+                    println!(
+                        "Warning: output port {} is not sensitive, while marked as such.",
+                        module.ports[con_id]
+                    )
                 }
-                (PortRole::Share(_), Some(false)) => {
-                    //if !wire_state.glitch_sensitivity.is_empty() {
-                    if !wire_state.sensitivity.is_empty() {
-                        bail!(
+            }
+            (PortRole::Share(_), Some(false)) => {
+                if !probed_sensitivity.is_empty() {
+                    return Err(Violation::new(
+                        ViolationKind::OutputPort,
+                        format!(
                             "Output share {} is not at a valid latency, but it is (glitch-)sensitive for shares {}.",
-                            module.ports[*con_id],
-                            //wire_state.glitch_sensitivity
-                            wire_state.sensitivity
-                        );
-                    }
+                            module.ports[con_id],
+                            probed_sensitivity
+                        ),
+                    )
+                    .with_shares([probed_sensitivity.clone()]));
                 }
-                (PortRole::Control, _) => {
-                    if !wire_state.glitch_sensitivity.is_empty() {
-                        bail!(
+            }
+            (PortRole::Control, _) => {
+                if !wire_state.glitch_sensitivity.is_empty() {
+                    return Err(Violation::new(
+                        ViolationKind::ControlNotDeterministic,
+                        format!(
                             "Output {} is a control, but it is (glitch-)sensitive for shares {}.",
-                            module.ports[*con_id],
-                            wire_state.glitch_sensitivity
-                        );
-                    } else if !wire_state.deterministic {
-                        bail!(
+                            module.ports[con_id], wire_state.glitch_sensitivity
+                        ),
+                    )
+                    .with_shares([wire_state.glitch_sensitivity.clone()]));
+                } else if !wire_state.deterministic {
+                    return Err(Violation::new(
+                        ViolationKind::ControlNotDeterministic,
+                        format!(
                             "Output {} is a control, but it is not deterministic.",
-                            module.ports[*con_id],
-                        );
-                    }
-                }
-                (PortRole::Share(_), None) | (PortRole::Random(_), _) => {
-                    unreachable!()
+                            module.ports[con_id],
+                        ),
+                    ));
                 }
             }
+            (PortRole::Share(_), None) | (PortRole::Random(_), _) => {
+                unreachable!()
+            }
         }
         Ok(())
     }
@@ -526,3 +1141,117 @@ impl RndStatus {
         self.fresh_uses.push((inst, lat));
     }
 }
+
+/// Input port of the module connected to the given wire.
+fn input_port(module: &Module, wire_name: &WireName) -> Option<InputId> {
+    module
+        .input_ports
+        .iter_enumerated()
+        .find_map(|(input_id, con_id)| {
+            let port = &module.ports[*con_id];
+            (port.name() == wire_name.name() && port.offset == wire_name.offset).then_some(input_id)
+        })
+}
+
+/// Wire of a module, looked up in the netlist (which contains all the named wires).
+fn module_wire_id(
+    netlist: &Netlist,
+    yosys_netlist: &yosys::Netlist,
+    module_id: ModuleId,
+    wire_name: &WireName,
+) -> Result<WireId> {
+    let module = netlist.module(module_id);
+    let bit = yosys_netlist.modules[&module.name]
+        .netnames
+        .get(wire_name.name())
+        .and_then(|netname| netname.bits.get(wire_name.offset))
+        .ok_or_else(|| anyhow!("Wire {} not found in module {}.", wire_name, module.name))?;
+    WireId::try_from(*bit)
+}
+
+/// Collect the state elements that drive the output `out` of the module, through buffers and
+/// module boundaries.
+fn find_output_state(
+    netlist: &Netlist,
+    module_id: ModuleId,
+    out: OutputId,
+    path: &mut Vec<String>,
+    res: &mut Vec<OutputState>,
+) {
+    let module = netlist.module(module_id);
+    let mut wire = module.connection_wires[module.output_ports[out]];
+    loop {
+        let (inst_id, src_out) = module.wires[wire].source;
+        let instance = &module.instances[inst_id];
+        match &instance.architecture {
+            InstanceType::Gate(gate @ Gate::CombUnitary(CombUnitary::Buf)) => {
+                wire = instance.connections[gate.input_ports()[InputId::from_usize(0)]];
+                continue;
+            }
+            InstanceType::Gate(Gate::Dff(_) | Gate::Latch(_)) => {
+                path.push(instance.name.clone());
+                res.push(OutputState {
+                    path: path.clone(),
+                    stage: None,
+                });
+                path.pop();
+            }
+            InstanceType::Module(submodule_id) => {
+                path.push(instance.name.clone());
+                if let Some(gadget) = netlist.gadget(*submodule_id) {
+                    let submodule = netlist.module(*submodule_id);
+                    res.push(OutputState {
+                        path: path.clone(),
+                        stage: Some(gadget.latency[submodule.output_ports[src_out]]),
+                    });
+                } else {
+                    find_output_state(netlist, *submodule_id, src_out, path, res);
+                }
+                path.pop();
+            }
+            _ => {}
+        }
+        return;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::share_set::tests::set;
+
+    #[test]
+    fn probed_overlapping_sets() {
+        // d=4, t=2: {0, 1} and {1, 2} cover only 3 shares with 2 probes.
+        let mut probed = ProbedShares::new(2, 4);
+        assert!(probed.observe(&set(&[0, 1])).is_ok());
+        assert!(probed.observe(&set(&[1, 2])).is_ok());
+        assert!(probed.observe(&set(&[0, 2])).is_ok());
+        // Subset of an observed set.
+        assert!(probed.observe(&set(&[1])).is_ok());
+    }
+
+    #[test]
+    fn probed_disjoint_sets() {
+        // d=4, t=2: {0, 1} and {2, 3} cover all the shares with 2 probes.
+        let mut probed = ProbedShares::new(2, 4);
+        assert!(probed.observe(&set(&[0, 1])).is_ok());
+        assert!(probed.observe(&set(&[1, 2])).is_ok());
+        assert_eq!(probed.observe(&set(&[2, 3])), Err(vec![set(&[0, 1])]));
+        // d=4, t=2: {0, 1, 2} and a single-share probe.
+        assert_eq!(probed.observe(&set(&[0, 1, 2])), Err(vec![]));
+        // Reset at the start of an execution.
+        probed.reset();
+        assert!(probed.observe(&set(&[2, 3])).is_ok());
+    }
+
+    #[test]
+    fn probed_order_d_minus_1() {
+        // t=d-1: any multi-share probe, with d-2 single-share probes, covers all the shares.
+        let mut probed = ProbedShares::new(2, 3);
+        assert!(probed.observe(&set(&[1])).is_ok());
+        assert_eq!(probed.observe(&set(&[0, 2])), Err(vec![]));
+        let mut probed = ProbedShares::new(3, 4);
+        assert_eq!(probed.observe(&set(&[0, 3])), Err(vec![]));
+    }
+}
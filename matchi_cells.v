@@ -34,6 +34,32 @@ module MUX(input wire A, input wire B, input wire S, output wire Y);
 assign Y = S ? B  :A;
 endmodule
 
+module HA(input wire A, input wire B, output wire S, output wire CO);
+assign S = A ^ B;
+assign CO = A & B;
+endmodule
+
+module FA(input wire A, input wire B, input wire CI, output wire S, output wire CO);
+assign S = A ^ B ^ CI;
+assign CO = (A & B) | (CI & (A ^ B));
+endmodule
+
 module DFF(input wire C, input wire D, output reg Q);
 always @(posedge C) Q <= D;
 endmodule
+
+module DFFE(input wire C, input wire D, input wire E, output reg Q);
+always @(posedge C) if (E) Q <= D;
+endmodule
+
+module SDFF(input wire C, input wire D, input wire R, output reg Q);
+always @(posedge C) Q <= R ? 1'b0 : D;
+endmodule
+
+module SDFFE(input wire C, input wire D, input wire E, input wire R, output reg Q);
+always @(posedge C) if (R) Q <= 1'b0; else if (E) Q <= D;
+endmodule
+
+module LATCH(input wire E, input wire D, output reg Q);
+always @(*) if (E) Q = D;
+endmodule